@@ -4,7 +4,10 @@ use std::{fs, path::PathBuf, process::exit};
 
 use anyhow::{Context, Result};
 use clap::Clap;
-use gfroerli_common::config::Config;
+use gfroerli_common::{
+    config::{self, Config, ConfigVersion},
+    config_slots::{self, CONFIG_SLOTS_SIZE},
+};
 use probe_rs::{
     config::{MemoryRegion, NvmRegion},
     flashing::{BinOptions, FlashLoader, FlashProgress, ProgressEvent},
@@ -24,10 +27,20 @@ fn main() -> Result<()> {
     // Parse command line args
     let opts: Opts = Opts::parse();
 
-    // Parse config
+    // Parse and validate config
     let config_source = fs::read_to_string(&opts.config).context("Could not read config file")?;
-    let config: Config = toml::from_str(&config_source).context("Could not parse config file")?;
-    let data = config.serialize();
+    let config: Config = toml::from_str(&config_source).context("Invalid config file")?;
+    if config.version != ConfigVersion::V2 {
+        println!(
+            "Note: Config format version {} is written as version {}",
+            config.version,
+            ConfigVersion::V2
+        );
+    }
+
+    // Write the config to slot A and clear slot B, so that the flashed config
+    // takes precedence over configs written by the firmware.
+    let data = config_slots::initial_image(&config);
 
     // Get a list of all available debug probes
     let probes = Probe::list_all();
@@ -48,10 +61,12 @@ fn main() -> Result<()> {
     println!("Attaching chip");
     let mut session = probe.attach_under_reset("STM32L071KBTx")?;
 
-    // Memory map: We only include the EEPROM memory region. This way, we can
-    // also prevent accidentally writing to flash.
+    // Memory map: We only include the EEPROM region of the config slots. This
+    // way, we can also prevent accidentally writing to flash or overwriting
+    // the other EEPROM regions.
+    let base_addr = config::BASE_ADDR as u32;
     let memory_map = vec![MemoryRegion::Nvm(NvmRegion {
-        range: (0x0808_0000..0x0808_0200),
+        range: (base_addr..base_addr + CONFIG_SLOTS_SIZE as u32),
         is_boot_memory: false,
     })];
 
@@ -73,7 +88,7 @@ fn main() -> Result<()> {
             &mut buffer,
             &mut cursor,
             BinOptions {
-                base_address: Some(0x0808_0000),
+                base_address: Some(base_addr),
                 skip: 0,
             },
         )
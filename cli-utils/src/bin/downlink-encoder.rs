@@ -0,0 +1,108 @@
+//! Encode a downlink command for remote configuration.
+//!
+//! The encoded command is printed as hex and base64, so that it can be
+//! scheduled as downlink on FPort 10 (e.g. in the TTN console).
+
+use anyhow::Result;
+use clap::Clap;
+use gfroerli_common::{
+    calibration::{Calibration, SUPPLY_GAIN_UNITY},
+    command::{Command, FPORT_COMMAND, MAX_COMMAND_LEN},
+    config::MAX_PROBES,
+};
+
+#[derive(Clap)]
+struct Opts {
+    #[clap(subcommand)]
+    command: Subcommand,
+}
+
+#[derive(Clap)]
+enum Subcommand {
+    /// Set the wakeup interval in seconds.
+    WakeupInterval { seconds: u16 },
+    /// Set the measurement intervals (every n-th wakeup).
+    Intervals {
+        nth_water_temp: u8,
+        nth_temp_humi: u8,
+        nth_voltage: u8,
+    },
+    /// Set the LoRaWAN data rate (0-5).
+    DataRate { data_rate: u8 },
+    /// Set the calibration.
+    Calibration {
+        /// Water temperature offsets in 0.01 °C, comma separated in the
+        /// order of the probes (missing offsets are 0)
+        #[clap(
+            long,
+            use_delimiter = true,
+            max_values = MAX_PROBES,
+            allow_hyphen_values = true
+        )]
+        water_temp_offsets: Vec<i16>,
+        /// SHT temperature offset in 0.01 °C
+        #[clap(long, default_value = "0", allow_hyphen_values = true)]
+        sht_temp_offset: i16,
+        /// SHT relative humidity offset in 0.01 %RH
+        #[clap(long, default_value = "0", allow_hyphen_values = true)]
+        sht_rh_offset: i16,
+        /// Supply voltage gain in 1/10000
+        #[clap(long, default_value = "10000")]
+        supply_gain: u16,
+    },
+}
+
+impl From<Subcommand> for Command {
+    fn from(subcommand: Subcommand) -> Self {
+        match subcommand {
+            Subcommand::WakeupInterval { seconds } => Command::SetWakeupInterval(seconds),
+            Subcommand::Intervals {
+                nth_water_temp,
+                nth_temp_humi,
+                nth_voltage,
+            } => Command::SetIntervals {
+                nth_water_temp,
+                nth_temp_humi,
+                nth_voltage,
+            },
+            Subcommand::DataRate { data_rate } => Command::SetDataRate(data_rate),
+            Subcommand::Calibration {
+                water_temp_offsets: offsets,
+                sht_temp_offset,
+                sht_rh_offset,
+                supply_gain,
+            } => {
+                let mut water_temp_offsets = [0; MAX_PROBES];
+                water_temp_offsets[..offsets.len()].copy_from_slice(&offsets);
+                Command::SetCalibration(Calibration {
+                    water_temp_offsets,
+                    sht_temp_offset,
+                    sht_rh_offset,
+                    supply_gain,
+                })
+            }
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    let opts: Opts = Opts::parse();
+
+    let command = Command::from(opts.command);
+    let mut buf = [0; MAX_COMMAND_LEN];
+    let length = command.encode(&mut buf);
+    let data = &buf[..length];
+
+    println!("Command: {:?}", command);
+    if let Command::SetCalibration(calibration) = command {
+        println!(
+            "  Supply gain: {:.4}",
+            calibration.supply_gain as f32 / SUPPLY_GAIN_UNITY as f32
+        );
+    }
+    println!("FPort:   {}", FPORT_COMMAND);
+    println!("Hex:     {}", hex::encode(data));
+    println!("Base64:  {}", base64::encode(data));
+
+    Ok(())
+}
@@ -0,0 +1,349 @@
+//! Generate the TTN v3 uplink payload formatter (JavaScript) from the message
+//! definitions in `gfroerli_common::measurement`.
+//!
+//! With `--vectors`, golden test vectors (payload bytes and the expected
+//! decoded JSON) are written instead. They are consumed by the Rust decoder
+//! tests in `common` and by `ttn/test-formatter.js`, so that both decoders are
+//! checked against the same data.
+
+use std::collections::BTreeSet;
+
+use anyhow::Result;
+use clap::Clap;
+use gfroerli_common::measurement::{
+    decode_uplink, Conversion, DecodedMeasurement, EncodedMeasurement, Field, FieldSchema,
+    MeasurementMessage, StatusFlags, DATA_MASK_BITS_PER_BYTE, FPORT_LEGACY, FPORT_MEASUREMENT, I12,
+    LEGACY_MSG_LEN, MAX_DATA_MASK_LEN, MAX_MSG_LEN, SCHEMA, U12,
+};
+use serde::Serialize;
+
+/// Print the TTN payload formatter (or the golden test vectors) to stdout.
+#[derive(Clap)]
+struct Opts {
+    /// Print golden test vectors (JSON) instead of the payload formatter.
+    #[clap(long)]
+    vectors: bool,
+}
+
+/// Key of a field in the decoded JSON object.
+///
+/// Multiple fields may map to the same key, in that case the field with the
+/// higher data mask bit takes precedence (like in `DecodedMeasurement`).
+fn output_key(field: Field) -> &'static str {
+    match field {
+        Field::TWater | Field::TWaterSigned => "t_water",
+        Field::TInside => "t_inside",
+        Field::RhInside => "rh_inside",
+        Field::VSupply => "v_supply",
+        Field::Status | Field::Status2 => "status",
+        Field::TWater2 => "t_water_2",
+        Field::TWater3 => "t_water_3",
+        Field::TWater4 => "t_water_4",
+    }
+}
+
+/// JavaScript expression implementing the conversion of the raw value `v`.
+///
+/// `previous` is the value already decoded for the same key. The status
+/// fields are combined into a single value, like in `StatusFlags`.
+fn js_conversion(field: &FieldSchema) -> String {
+    match field.conversion {
+        Conversion::Ds18b20 => "v / 16".into(),
+        Conversion::Shtc3Temperature => "-45 + 175 * (v / 65536)".into(),
+        Conversion::Shtc3Humidity => "100 * (v / 65536)".into(),
+        Conversion::Supply => "v + 2000".into(),
+        Conversion::Flags => match field.field.status_shift() {
+            0 => "(previous || 0) | v".into(),
+            shift => format!("(previous || 0) | v << {}", shift),
+        },
+    }
+}
+
+const FUNCTIONS: &str = r#"function decodeLegacy(bytes) {
+  if (bytes.length === 0) throw new Error("Empty message");
+  if (bytes.length < LEGACY_MSG_LEN) throw new Error("Message too short");
+  if (bytes.length > LEGACY_MSG_LEN) throw new Error("Message too long");
+  var view = new DataView(new Uint8Array(bytes).buffer);
+  function available(offset) {
+    var value = view.getFloat32(offset, true);
+    return isNaN(value) ? null : value;
+  }
+  var vSupply = available(12);
+  var data = {};
+  KEYS.forEach(function (key) {
+    data[key] = null;
+  });
+  data.t_water = available(0);
+  data.t_inside = available(4);
+  data.rh_inside = available(8);
+  // Volts to millivolts, saturating like a float to int cast in Rust
+  data.v_supply = vSupply === null ? null : Math.min(Math.max(Math.floor(vSupply * 1000 + 0.5), 0), 65535);
+  return data;
+}
+
+function decodeMeasurement(bytes) {
+  if (bytes.length === 0) throw new Error("Empty message");
+  var input = bytes.slice(0, MAX_MSG_LEN);
+
+  // Read data mask bytes until the continuation bit is not set
+  var dataMask = 0;
+  var maskLen = 0;
+  while (true) {
+    if (maskLen >= input.length) throw new Error("Message too short");
+    var maskByte = input[maskLen];
+    dataMask |= (maskByte & 0x7f) << (maskLen * DATA_MASK_BITS_PER_BYTE);
+    maskLen += 1;
+    if ((maskByte & 0x80) === 0) break;
+    if (maskLen === MAX_DATA_MASK_LEN) break;
+  }
+  if ((input[maskLen - 1] & 0x80) !== 0 || (dataMask & ~KNOWN_DATA_MASK_BITS) !== 0) {
+    throw new Error("Unsupported data mask (0b" + (dataMask >>> 0).toString(2) + ")");
+  }
+
+  var bitIndex = maskLen * 8;
+  function readBits(width) {
+    var value = 0;
+    for (var i = 0; i < width; i++) {
+      var bit = bitIndex + i;
+      value = value * 2 + ((input[bit >> 3] >> (7 - (bit & 7))) & 1);
+    }
+    bitIndex += width;
+    return value;
+  }
+
+  var data = {};
+  KEYS.forEach(function (key) {
+    data[key] = null;
+  });
+  FIELDS.forEach(function (field) {
+    if ((dataMask & (1 << field.maskBit)) === 0) return;
+    if (bitIndex + field.width > input.length * 8) throw new Error("Message too short");
+    var v = readBits(field.width);
+    if (field.signed && v >= Math.pow(2, field.width - 1)) v -= Math.pow(2, field.width);
+    data[field.key] = field.convert(v, data[field.key]);
+  });
+  if (bytes.length > Math.ceil(bitIndex / 8)) throw new Error("Message too long");
+  return data;
+}
+
+function decodeUplink(input) {
+  try {
+    switch (input.fPort) {
+      case FPORT_LEGACY:
+        return { data: decodeLegacy(input.bytes) };
+      case FPORT_MEASUREMENT:
+        return { data: decodeMeasurement(input.bytes) };
+      default:
+        throw new Error("Unsupported FPort (" + input.fPort + ")");
+    }
+  } catch (e) {
+    return { errors: [e.message] };
+  }
+}
+
+if (typeof module !== "undefined") {
+  module.exports = { decodeUplink: decodeUplink };
+}"#;
+
+fn print_formatter() {
+    let known_data_mask_bits = SCHEMA
+        .iter()
+        .fold(0u32, |mask, field| mask | 1 << field.field.mask_bit());
+    // Unique keys, in the order of the schema
+    let mut seen = BTreeSet::new();
+    let keys: Vec<&str> = SCHEMA
+        .iter()
+        .map(|field| output_key(field.field))
+        .filter(|key| seen.insert(*key))
+        .collect();
+
+    println!("// TTN v3 uplink payload formatter for Gfrörli sensors.");
+    println!("//");
+    println!("// Generated by `cargo run --bin ttn-formatter`, do not edit manually.");
+    println!();
+    println!("var FPORT_LEGACY = {};", FPORT_LEGACY);
+    println!("var FPORT_MEASUREMENT = {};", FPORT_MEASUREMENT);
+    println!("var LEGACY_MSG_LEN = {};", LEGACY_MSG_LEN);
+    println!("var MAX_MSG_LEN = {};", MAX_MSG_LEN);
+    println!("var MAX_DATA_MASK_LEN = {};", MAX_DATA_MASK_LEN);
+    println!("var DATA_MASK_BITS_PER_BYTE = {};", DATA_MASK_BITS_PER_BYTE);
+    println!("var KNOWN_DATA_MASK_BITS = 0b{:b};", known_data_mask_bits);
+    println!();
+    println!("// Keys of the decoded object");
+    println!("var KEYS = {:?};", keys);
+    println!();
+    println!("// Fields of the measurement format, in the order of their data mask bits");
+    println!("var FIELDS = [");
+    for field in SCHEMA.iter() {
+        let unit = match field.unit {
+            "" => String::new(),
+            unit => format!(" ({})", unit),
+        };
+        println!(
+            "  {{ maskBit: {}, width: {}, signed: {}, key: {:?}, convert: function (v, previous) {{ return {}; }} }}, // {}{}",
+            field.field.mask_bit(),
+            field.width,
+            field.signed,
+            output_key(field.field),
+            js_conversion(field),
+            field.name,
+            unit,
+        );
+    }
+    println!("];");
+    println!();
+    println!("{}", FUNCTIONS);
+}
+
+/// A golden test vector.
+#[derive(Serialize)]
+struct TestVector {
+    description: &'static str,
+    fport: u8,
+    /// Payload as hex string
+    payload: String,
+    /// The decoded measurement, if decoding succeeds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expected: Option<DecodedMeasurement>,
+    /// The error message, if decoding fails
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl TestVector {
+    fn new(description: &'static str, fport: u8, payload: &[u8]) -> Self {
+        let result = decode_uplink(fport, payload);
+        Self {
+            description,
+            fport,
+            payload: hex::encode(payload),
+            error: result.as_ref().err().map(ToString::to_string),
+            expected: result.ok(),
+        }
+    }
+}
+
+fn legacy(values: [f32; 4]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+fn measurement(message: MeasurementMessage) -> Vec<u8> {
+    let mut buf = EncodedMeasurement([0u8; MAX_MSG_LEN]);
+    let length = message.encode(&mut buf);
+    buf.0[..length].to_vec()
+}
+
+fn test_vectors() -> Vec<TestVector> {
+    let all = MeasurementMessage {
+        t_water: Some(U12::new(0x158)),
+        t_inside: Some(0x6A3D),
+        rh_inside: Some(0x8E14),
+        v_supply: Some(U12::new(0x4C4)),
+        t_water_signed: Some(I12::new(0x158)),
+        status: Some(StatusFlags::from_bits(0b01_0001_0000)),
+        t_water_2: Some(I12::new(0x140)),
+        t_water_3: Some(I12::new(0x0F0)),
+        t_water_4: Some(I12::new(-8)),
+    };
+    let mut too_long = measurement(all);
+    too_long.push(0);
+    vec![
+        TestVector::new(
+            "Legacy format",
+            FPORT_LEGACY,
+            &legacy([21.5, 22.25, 55.5, 3.3]),
+        ),
+        TestVector::new(
+            "Legacy format, no water temperature",
+            FPORT_LEGACY,
+            &legacy([f32::NAN, 22.25, 55.5, 3.3]),
+        ),
+        TestVector::new("Legacy format, too short", FPORT_LEGACY, &[0; 15]),
+        TestVector::new(
+            "No values",
+            FPORT_MEASUREMENT,
+            &measurement(MeasurementMessage::default()),
+        ),
+        TestVector::new(
+            "Unsigned water temperature",
+            FPORT_MEASUREMENT,
+            &measurement(MeasurementMessage {
+                t_water: Some(U12::new(0x05A)),
+                ..Default::default()
+            }),
+        ),
+        TestVector::new(
+            "Negative water temperature",
+            FPORT_MEASUREMENT,
+            &measurement(MeasurementMessage {
+                t_water_signed: Some(I12::new(-160)),
+                ..Default::default()
+            }),
+        ),
+        TestVector::new(
+            "Inside temperature, humidity and supply voltage",
+            FPORT_MEASUREMENT,
+            &measurement(MeasurementMessage {
+                t_inside: Some(0x6A3D),
+                rh_inside: Some(0x8E14),
+                v_supply: Some(U12::new(0x4C4)),
+                ..Default::default()
+            }),
+        ),
+        TestVector::new(
+            "Status flags",
+            FPORT_MEASUREMENT,
+            &measurement(MeasurementMessage {
+                t_water_signed: Some(I12::new(0x550)),
+                status: Some(StatusFlags::from_bits(0b0100_0011)),
+                ..Default::default()
+            }),
+        ),
+        TestVector::new(
+            "DS18B20 CRC error and out-of-range value",
+            FPORT_MEASUREMENT,
+            &measurement(MeasurementMessage {
+                status: Some(StatusFlags::from_bits(0b11_0000_0000)),
+                ..Default::default()
+            }),
+        ),
+        TestVector::new(
+            "Multiple water temperature probes",
+            FPORT_MEASUREMENT,
+            &measurement(MeasurementMessage {
+                t_water_signed: Some(I12::new(0x150)),
+                t_water_3: Some(I12::new(0x0C8)),
+                t_water_4: Some(I12::new(-32)),
+                ..Default::default()
+            }),
+        ),
+        TestVector::new("All values", FPORT_MEASUREMENT, &measurement(all)),
+        TestVector::new("Empty", FPORT_MEASUREMENT, &[]),
+        TestVector::new("Truncated", FPORT_MEASUREMENT, &[0b0000_1001, 0x05, 0xA0]),
+        TestVector::new("Trailing bytes", FPORT_MEASUREMENT, &too_long),
+        TestVector::new(
+            "Reserved data mask bit",
+            FPORT_MEASUREMENT,
+            &[0b1000_0000, 0b0000_1000],
+        ),
+        TestVector::new(
+            "Data mask extension without values",
+            FPORT_MEASUREMENT,
+            &[0b1000_0000, 0b0000_0001],
+        ),
+        TestVector::new("Unknown FPort", 42, &[0x00]),
+    ]
+}
+
+fn main() -> Result<()> {
+    let opts: Opts = Opts::parse();
+    if opts.vectors {
+        println!("{}", serde_json::to_string_pretty(&test_vectors())?);
+    } else {
+        print_formatter();
+    }
+    Ok(())
+}
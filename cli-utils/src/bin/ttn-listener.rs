@@ -1,8 +1,14 @@
-use std::{thread, time::Duration};
+use std::{collections::HashMap, thread, time::Duration};
 
 use anyhow::{Context, Result};
 use clap::Clap;
+use gfroerli_common::{
+    command::{CommandAck, FPORT_COMMAND},
+    panic::{PanicRecord, FPORT_PANIC},
+    status::{StatusMessage, Version, FPORT_STATUS},
+};
 use paho_mqtt as mqtt;
+use serde_json::Value;
 
 /// Attempt to reconnect to the broker. It can be called after connection is lost. In this example,
 /// we try to reconnect several times, with a few second pause between each attempt. A real system
@@ -20,6 +26,83 @@ fn try_reconnect(client: &mqtt::Client) -> bool {
     false
 }
 
+/// Format an optional version, unknown versions are shown as `?`.
+fn format_version(version: Option<Version>) -> String {
+    version.map_or_else(|| "?".to_string(), |v| v.to_string())
+}
+
+/// Pretty-print the decoded payload of an uplink message, if it is known.
+///
+/// `probe_ids` holds the last probe ID reported by each device, a change of
+/// the probe ID (i.e. a swapped water temperature probe) is flagged.
+fn print_uplink(msg: &mqtt::Message, probe_ids: &mut HashMap<String, u32>) {
+    let uplink: Value = match serde_json::from_slice(msg.payload()) {
+        Ok(uplink) => uplink,
+        Err(_) => return,
+    };
+    let device_id = uplink["end_device_ids"]["device_id"]
+        .as_str()
+        .unwrap_or("?");
+    let fport = uplink["uplink_message"]["f_port"].as_u64();
+    let payload = uplink["uplink_message"]["frm_payload"]
+        .as_str()
+        .and_then(|payload| base64::decode(payload).ok());
+    if let (Some(fport), Some(payload)) = (fport, payload) {
+        if fport == FPORT_PANIC as u64 {
+            match PanicRecord::decode(&payload) {
+                Ok(record) => println!(
+                    "🔥 Device {} panicked: hash={:08x} location={}",
+                    device_id,
+                    record.hash,
+                    record.location()
+                ),
+                Err(e) => println!("Device {} sent invalid panic record: {}", device_id, e),
+            }
+        } else if fport == FPORT_STATUS as u64 {
+            match StatusMessage::decode(&payload) {
+                Ok(status) => {
+                    println!(
+                        "📣 Device {} booted: firmware={} hardware={} rn2483={} config={} probe={}{}",
+                        device_id,
+                        format_version(status.firmware_version),
+                        status.hardware_version,
+                        format_version(status.rn2483_version),
+                        status.config_version,
+                        status
+                            .probe_id
+                            .map_or_else(|| "-".to_string(), |id| format!("{:08x}", id)),
+                        if status.panicked { " (panicked)" } else { "" },
+                    );
+                    if let Some(probe_id) = status.probe_id {
+                        if let Some(previous) = probe_ids
+                            .insert(device_id.to_string(), probe_id)
+                            .filter(|previous| *previous != probe_id)
+                        {
+                            println!(
+                                "⚠️  Device {} probe ID changed from {:08x} to {:08x}, the water \
+                                 temperature probe was swapped",
+                                device_id, previous, probe_id
+                            );
+                        }
+                    }
+                }
+                Err(e) => println!("Device {} sent invalid status message: {}", device_id, e),
+            }
+        } else if fport == FPORT_COMMAND as u64 {
+            match CommandAck::decode(&payload) {
+                Ok(ack) => println!(
+                    "🔧 Device {} confirmed command 0x{:02x}: {}",
+                    device_id, ack.opcode, ack.result
+                ),
+                Err(e) => println!(
+                    "Device {} sent invalid command acknowledgement: {}",
+                    device_id, e
+                ),
+            }
+        }
+    }
+}
+
 #[derive(Clap)]
 struct Opts {
     #[clap(short, long, default_value = "tcp://eu1.cloud.thethings.network:1883")]
@@ -86,9 +169,11 @@ fn main() -> Result<()> {
     // If we get a None message, check if we got disconnected,
     // and then try a reconnect.
     println!("Waiting for messages...");
+    let mut probe_ids = HashMap::new();
     for msg in rx.iter() {
         if let Some(msg) = msg {
             println!("{}", msg);
+            print_uplink(&msg, &mut probe_ids);
         } else if client.is_connected() || !try_reconnect(&client) {
             break;
         }
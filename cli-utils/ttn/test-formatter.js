@@ -0,0 +1,44 @@
+// Check a generated TTN payload formatter against the golden test vectors.
+//
+// Usage: node test-formatter.js <formatter.js> <uplinks.json>
+
+var fs = require("fs");
+var path = require("path");
+
+var formatter = require(path.resolve(process.argv[2]));
+var vectors = JSON.parse(fs.readFileSync(process.argv[3], "utf8"));
+
+// The Rust decoder calculates with f32, JavaScript with f64
+function approxEqual(actual, expected) {
+  if (actual === null || expected === null) return actual === expected;
+  return Math.abs(actual - expected) <= 1e-4 * Math.max(1, Math.abs(expected));
+}
+
+var failures = 0;
+vectors.forEach(function (vector) {
+  var bytes = [];
+  for (var i = 0; i < vector.payload.length; i += 2) {
+    bytes.push(parseInt(vector.payload.substr(i, 2), 16));
+  }
+  var result = formatter.decodeUplink({ fPort: vector.fport, bytes: bytes });
+  var ok;
+  if (vector.error !== undefined) {
+    ok = result.errors !== undefined && result.errors[0] === vector.error;
+  } else {
+    ok =
+      result.data !== undefined &&
+      JSON.stringify(Object.keys(result.data)) === JSON.stringify(Object.keys(vector.expected)) &&
+      Object.keys(vector.expected).every(function (key) {
+        return approxEqual(result.data[key], vector.expected[key]);
+      });
+  }
+  if (!ok) {
+    failures += 1;
+    console.log("FAIL: " + vector.description);
+    console.log("  expected: " + JSON.stringify(vector.error !== undefined ? vector.error : vector.expected));
+    console.log("  actual:   " + JSON.stringify(result));
+  }
+});
+
+console.log(vectors.length - failures + "/" + vectors.length + " test vectors passed");
+process.exit(failures === 0 ? 0 : 1);
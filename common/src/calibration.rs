@@ -0,0 +1,183 @@
+//! Per-device sensor calibration.
+//!
+//! The calibration is stored in the device configuration and applied to the
+//! raw sensor values before they are encoded, so the backend receives the
+//! corrected values without knowing about the calibration.
+
+use crate::{config::MAX_PROBES, measurement::U12};
+
+/// Supply voltage gain that leaves the value unchanged (1.0).
+pub const SUPPLY_GAIN_UNITY: u16 = 10_000;
+
+/// Calibration values of a device.
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(default))]
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Calibration {
+    /// Offsets added to the DS18B20 water temperatures (in 0.01 °C), one per
+    /// probe position (see [`Config::probe_order`](crate::config::Config))
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "crate::config::deserialize_per_probe")
+    )]
+    pub water_temp_offsets: [i16; MAX_PROBES],
+    /// Offset added to the SHTC3 temperature (in 0.01 °C)
+    pub sht_temp_offset: i16,
+    /// Offset added to the SHTC3 relative humidity (in 0.01 %RH)
+    pub sht_rh_offset: i16,
+    /// Gain applied to the supply voltage (in 1/10000, see
+    /// [`SUPPLY_GAIN_UNITY`])
+    pub supply_gain: u16,
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self {
+            water_temp_offsets: [0; MAX_PROBES],
+            sht_temp_offset: 0,
+            sht_rh_offset: 0,
+            supply_gain: SUPPLY_GAIN_UNITY,
+        }
+    }
+}
+
+/// Divide and round to the nearest integer (away from zero on ties).
+fn div_round(numerator: i32, denominator: i32) -> i32 {
+    if numerator >= 0 {
+        (numerator + denominator / 2) / denominator
+    } else {
+        (numerator - denominator / 2) / denominator
+    }
+}
+
+/// Add a signed offset to an unsigned raw value, saturating at the bounds.
+fn add_offset_u16(raw: u16, offset: i32) -> u16 {
+    (raw as i32 + offset).clamp(0, u16::MAX as i32) as u16
+}
+
+impl Calibration {
+    /// Apply the water temperature offset of the probe at position `probe` to
+    /// a raw DS18B20 value (in 1/16 °C).
+    ///
+    /// Panics if `probe` is not smaller than `MAX_PROBES`.
+    pub fn apply_water_temp(&self, probe: usize, raw: i16) -> i16 {
+        let offset = div_round(self.water_temp_offsets[probe] as i32 * 16, 100);
+        (raw as i32 + offset).clamp(i16::MIN as i32, i16::MAX as i32) as i16
+    }
+
+    /// Apply the temperature offset to a raw SHTC3 temperature value
+    /// (`-45 + 175 * (v / 2^16)` °C).
+    pub fn apply_sht_temperature(&self, raw: u16) -> u16 {
+        add_offset_u16(raw, div_round(self.sht_temp_offset as i32 * 65536, 17_500))
+    }
+
+    /// Apply the humidity offset to a raw SHTC3 humidity value
+    /// (`100 * (v / 2^16)` %RH).
+    pub fn apply_sht_humidity(&self, raw: u16) -> u16 {
+        add_offset_u16(raw, div_round(self.sht_rh_offset as i32 * 65536, 10_000))
+    }
+
+    /// Apply the gain to a raw supply voltage value (`v + 2000` mV).
+    pub fn apply_supply(&self, raw: U12) -> U12 {
+        let millivolts = raw.as_u16() as u32 + 2000;
+        let unity = SUPPLY_GAIN_UNITY as u32;
+        let calibrated = (millivolts * self.supply_gain as u32 + unity / 2) / unity;
+        U12::new(calibrated.saturating_sub(2000).min(u16::MAX as u32) as u16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::measurement::Conversion;
+
+    #[test]
+    fn test_default_is_identity() {
+        let calibration = Calibration::default();
+        for probe in 0..MAX_PROBES {
+            assert_eq!(calibration.apply_water_temp(probe, -100), -100);
+        }
+        assert_eq!(calibration.apply_sht_temperature(0x6000), 0x6000);
+        assert_eq!(calibration.apply_sht_humidity(0x8000), 0x8000);
+        assert_eq!(calibration.apply_supply(U12::new(1600)), U12::new(1600));
+    }
+
+    #[test]
+    fn test_water_temp_offset() {
+        let calibration = Calibration {
+            water_temp_offsets: [25, 0, 0, 0], // +0.25 °C
+            ..Default::default()
+        };
+        // 20.0 °C -> 20.25 °C
+        let raw = calibration.apply_water_temp(0, 320);
+        assert_eq!(raw, 324);
+        assert_eq!(Conversion::Ds18b20.apply(raw as i32), 20.25);
+
+        let calibration = Calibration {
+            water_temp_offsets: [-12, 0, 0, 0], // -0.12 °C, rounds to -2/16 °C
+            ..Default::default()
+        };
+        assert_eq!(calibration.apply_water_temp(0, 0), -2);
+    }
+
+    #[test]
+    fn test_water_temp_offset_per_probe() {
+        let calibration = Calibration {
+            water_temp_offsets: [25, -50, 0, 100], // +0.25, -0.5, 0, +1 °C
+            ..Default::default()
+        };
+        assert_eq!(calibration.apply_water_temp(0, 320), 324);
+        assert_eq!(calibration.apply_water_temp(1, 320), 312);
+        assert_eq!(calibration.apply_water_temp(2, 320), 320);
+        assert_eq!(calibration.apply_water_temp(3, 320), 336);
+    }
+
+    #[test]
+    fn test_sht_offsets() {
+        let calibration = Calibration {
+            sht_temp_offset: -50, // -0.5 °C
+            sht_rh_offset: 150,   // +1.5 %RH
+            ..Default::default()
+        };
+
+        let raw_temperature = 0x6000; // 20.625 °C
+        let calibrated = calibration.apply_sht_temperature(raw_temperature);
+        assert_eq!(calibrated, raw_temperature - 187);
+        let celsius = Conversion::Shtc3Temperature.apply(calibrated as i32);
+        assert!((celsius - 20.125).abs() < 0.01, "{}", celsius);
+
+        let raw_humidity = 0x8000; // 50 %RH
+        let calibrated = calibration.apply_sht_humidity(raw_humidity);
+        assert_eq!(calibrated, raw_humidity + 983);
+        let percent = Conversion::Shtc3Humidity.apply(calibrated as i32);
+        assert!((percent - 51.5).abs() < 0.01, "{}", percent);
+
+        // Saturate at the bounds
+        assert_eq!(calibration.apply_sht_temperature(100), 0);
+        assert_eq!(calibration.apply_sht_humidity(0xFFF0), 0xFFFF);
+    }
+
+    #[test]
+    fn test_supply_gain() {
+        let calibration = Calibration {
+            supply_gain: 10_200, // 1.02
+            ..Default::default()
+        };
+        // 3600 mV -> 3672 mV
+        let calibrated = calibration.apply_supply(U12::new(1600));
+        assert_eq!(calibrated, U12::new(1672));
+        assert_eq!(Conversion::Supply.apply(calibrated.as_u16() as i32), 3672.0);
+
+        // Saturate at the bounds of the encoding
+        let calibration = Calibration {
+            supply_gain: 5_000, // 0.5
+            ..Default::default()
+        };
+        assert_eq!(calibration.apply_supply(U12::new(1000)), U12::new(0));
+        let calibration = Calibration {
+            supply_gain: 15_000, // 1.5
+            ..Default::default()
+        };
+        assert_eq!(calibration.apply_supply(U12::new(4000)), U12::new(0xFFF));
+    }
+}
@@ -0,0 +1,358 @@
+//! Downlink commands for remote configuration.
+//!
+//! A command is sent as downlink on FPort 10. It consists of an opcode byte,
+//! followed by the (big endian) arguments of the command. Every downlink
+//! contains exactly one command.
+//!
+//! The device applies the command to its configuration, persists the updated
+//! configuration and confirms the command with a [`CommandAck`] uplink on the
+//! same FPort.
+
+use core::{convert::TryInto, fmt};
+
+use crate::{
+    calibration::Calibration,
+    config::{Config, ConfigError, MAX_PROBES},
+    measurement::DecodeError,
+};
+
+/// FPort of downlink commands and their acknowledgements.
+pub const FPORT_COMMAND: u8 = 10;
+
+/// Maximum length of an encoded command.
+pub const MAX_COMMAND_LEN: usize = 15;
+
+/// Length of an encoded command acknowledgement.
+pub const COMMAND_ACK_LEN: usize = 2;
+
+/// A downlink command.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Command {
+    /// Set the wakeup interval in seconds (opcode `0x01`, u16)
+    SetWakeupInterval(u16),
+    /// Set the measurement intervals (opcode `0x02`, three u8: water
+    /// temperature, temperature/humidity and voltage)
+    SetIntervals {
+        nth_water_temp: u8,
+        nth_temp_humi: u8,
+        nth_voltage: u8,
+    },
+    /// Set the LoRaWAN data rate (opcode `0x03`, u8)
+    SetDataRate(u8),
+    /// Set the calibration (opcode `0x04`, `MAX_PROBES` i16 water temperature
+    /// offsets, two i16 SHTC3 offsets and the u16 supply gain, in the order of
+    /// the [`Calibration`] fields)
+    SetCalibration(Calibration),
+}
+
+impl Command {
+    /// Return the opcode of this command.
+    pub fn opcode(&self) -> u8 {
+        match self {
+            Self::SetWakeupInterval(_) => 0x01,
+            Self::SetIntervals { .. } => 0x02,
+            Self::SetDataRate(_) => 0x03,
+            Self::SetCalibration(_) => 0x04,
+        }
+    }
+
+    /// Encode the command into the buffer, return the encoded length.
+    pub fn encode(&self, buf: &mut [u8; MAX_COMMAND_LEN]) -> usize {
+        buf[0] = self.opcode();
+        let args = &mut buf[1..];
+        let args_len = match *self {
+            Self::SetWakeupInterval(seconds) => {
+                args[..2].copy_from_slice(&seconds.to_be_bytes());
+                2
+            }
+            Self::SetIntervals {
+                nth_water_temp,
+                nth_temp_humi,
+                nth_voltage,
+            } => {
+                args[..3].copy_from_slice(&[nth_water_temp, nth_temp_humi, nth_voltage]);
+                3
+            }
+            Self::SetDataRate(data_rate) => {
+                args[0] = data_rate;
+                1
+            }
+            Self::SetCalibration(calibration) => {
+                for (chunk, offset) in args
+                    .chunks_exact_mut(2)
+                    .zip(calibration.water_temp_offsets.iter())
+                {
+                    chunk.copy_from_slice(&offset.to_be_bytes());
+                }
+                let args = &mut args[2 * MAX_PROBES..];
+                args[0..2].copy_from_slice(&calibration.sht_temp_offset.to_be_bytes());
+                args[2..4].copy_from_slice(&calibration.sht_rh_offset.to_be_bytes());
+                args[4..6].copy_from_slice(&calibration.supply_gain.to_be_bytes());
+                2 * MAX_PROBES + 6
+            }
+        };
+        1 + args_len
+    }
+
+    /// Decode a command.
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        let (opcode, args) = data.split_first().ok_or(DecodeError::Empty)?;
+        let args_len = match opcode {
+            0x01 => 2,
+            0x02 => 3,
+            0x03 => 1,
+            0x04 => 2 * MAX_PROBES + 6,
+            other => return Err(DecodeError::UnsupportedCommand(*other)),
+        };
+        if args.len() < args_len {
+            return Err(DecodeError::TooShort);
+        }
+        if args.len() > args_len {
+            return Err(DecodeError::TooLong);
+        }
+        let u16_at =
+            |offset: usize| u16::from_be_bytes(args[offset..offset + 2].try_into().unwrap());
+        Ok(match opcode {
+            0x01 => Self::SetWakeupInterval(u16_at(0)),
+            0x02 => Self::SetIntervals {
+                nth_water_temp: args[0],
+                nth_temp_humi: args[1],
+                nth_voltage: args[2],
+            },
+            0x03 => Self::SetDataRate(args[0]),
+            _ => {
+                let mut water_temp_offsets = [0; MAX_PROBES];
+                for (probe, offset) in water_temp_offsets.iter_mut().enumerate() {
+                    *offset = u16_at(2 * probe) as i16;
+                }
+                Self::SetCalibration(Calibration {
+                    water_temp_offsets,
+                    sht_temp_offset: u16_at(2 * MAX_PROBES) as i16,
+                    sht_rh_offset: u16_at(2 * MAX_PROBES + 2) as i16,
+                    supply_gain: u16_at(2 * MAX_PROBES + 4),
+                })
+            }
+        })
+    }
+
+    /// Apply the command to the configuration.
+    ///
+    /// Returns the updated configuration, or the validation error if the
+    /// resulting configuration is not valid.
+    pub fn apply(&self, mut config: Config) -> Result<Config, ConfigError> {
+        match *self {
+            Self::SetWakeupInterval(seconds) => config.wakeup_interval_seconds = seconds,
+            Self::SetIntervals {
+                nth_water_temp,
+                nth_temp_humi,
+                nth_voltage,
+            } => {
+                config.nth_water_temp = nth_water_temp;
+                config.nth_temp_humi = nth_temp_humi;
+                config.nth_voltage = nth_voltage;
+            }
+            Self::SetDataRate(data_rate) => config.data_rate = data_rate,
+            Self::SetCalibration(calibration) => config.calibration = calibration,
+        }
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+/// Result of a downlink command, reported in the [`CommandAck`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[repr(u8)]
+pub enum CommandResult {
+    /// The command was applied and persisted
+    Applied = 0,
+    /// The command could not be decoded
+    Malformed = 1,
+    /// The resulting configuration would be invalid, the command was ignored
+    Invalid = 2,
+    /// The updated configuration could not be persisted
+    StorageError = 3,
+}
+
+impl CommandResult {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Applied),
+            1 => Some(Self::Malformed),
+            2 => Some(Self::Invalid),
+            3 => Some(Self::StorageError),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for CommandResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Applied => write!(f, "applied"),
+            Self::Malformed => write!(f, "malformed"),
+            Self::Invalid => write!(f, "invalid"),
+            Self::StorageError => write!(f, "storage error"),
+        }
+    }
+}
+
+/// Acknowledgement of a downlink command (FPort 10 uplink).
+///
+/// Format: The opcode of the command (u8, 0 if the downlink was empty),
+/// followed by the [`CommandResult`] (u8).
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct CommandAck {
+    pub opcode: u8,
+    pub result: CommandResult,
+}
+
+impl CommandAck {
+    pub fn encode(&self) -> [u8; COMMAND_ACK_LEN] {
+        [self.opcode, self.result as u8]
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        match *data {
+            [opcode, result] => Ok(Self {
+                opcode,
+                result: CommandResult::from_u8(result)
+                    .ok_or(DecodeError::UnsupportedCommandResult(result))?,
+            }),
+            [] | [_] => Err(DecodeError::TooShort),
+            _ => Err(DecodeError::TooLong),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::test_config;
+
+    fn config() -> Config {
+        Config {
+            batch_size: 0,
+            data_rate: 4,
+            ..test_config()
+        }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let commands = [
+            (Command::SetWakeupInterval(600), &[0x01, 0x02, 0x58][..]),
+            (
+                Command::SetIntervals {
+                    nth_water_temp: 1,
+                    nth_temp_humi: 2,
+                    nth_voltage: 12,
+                },
+                &[0x02, 1, 2, 12][..],
+            ),
+            (Command::SetDataRate(0), &[0x03, 0x00][..]),
+            (
+                Command::SetCalibration(Calibration {
+                    water_temp_offsets: [-15, 0, 20, 0],
+                    sht_temp_offset: 30,
+                    sht_rh_offset: -200,
+                    supply_gain: 9_800,
+                }),
+                &[
+                    0x04, 0xFF, 0xF1, 0x00, 0x00, 0x00, 0x14, 0x00, 0x00, 0x00, 0x1E, 0xFF, 0x38,
+                    0x26, 0x48,
+                ][..],
+            ),
+        ];
+        for (command, bytes) in commands.iter() {
+            let mut buf = [0; MAX_COMMAND_LEN];
+            let length = command.encode(&mut buf);
+            assert_eq!(&buf[..length], *bytes);
+            assert_eq!(Command::decode(bytes), Ok(*command));
+        }
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert_eq!(Command::decode(&[]), Err(DecodeError::Empty));
+        assert_eq!(
+            Command::decode(&[0x42, 0x00]),
+            Err(DecodeError::UnsupportedCommand(0x42))
+        );
+        assert_eq!(Command::decode(&[0x01, 0x02]), Err(DecodeError::TooShort));
+        assert_eq!(
+            Command::decode(&[0x03, 0x00, 0x00]),
+            Err(DecodeError::TooLong)
+        );
+    }
+
+    #[test]
+    fn test_apply() {
+        let config = Command::SetWakeupInterval(600).apply(config()).unwrap();
+        assert_eq!(config.wakeup_interval_seconds, 600);
+
+        let config = Command::SetIntervals {
+            nth_water_temp: 2,
+            nth_temp_humi: 3,
+            nth_voltage: 24,
+        }
+        .apply(config)
+        .unwrap();
+        assert_eq!(
+            (
+                config.nth_water_temp,
+                config.nth_temp_humi,
+                config.nth_voltage
+            ),
+            (2, 3, 24)
+        );
+
+        let config = Command::SetDataRate(0).apply(config).unwrap();
+        assert_eq!(config.data_rate, 0);
+
+        let calibration = Calibration {
+            water_temp_offsets: [10, -10, 0, 0],
+            ..Default::default()
+        };
+        let config = Command::SetCalibration(calibration).apply(config).unwrap();
+        assert_eq!(config.calibration, calibration);
+    }
+
+    #[test]
+    fn test_apply_invalid() {
+        assert_eq!(
+            Command::SetWakeupInterval(0).apply(config()),
+            Err(ConfigError::ZeroInterval("wakeup_interval_seconds"))
+        );
+        assert_eq!(
+            Command::SetDataRate(7).apply(config()),
+            Err(ConfigError::OutOfRange("data_rate"))
+        );
+        // A full batch would not fit into a single uplink at SF12
+        let batching = Config {
+            batch_size: 8,
+            ..config()
+        };
+        assert_eq!(
+            Command::SetDataRate(0).apply(batching),
+            Err(ConfigError::OutOfRange("batch_size"))
+        );
+    }
+
+    #[test]
+    fn test_ack() {
+        let ack = CommandAck {
+            opcode: 0x03,
+            result: CommandResult::Invalid,
+        };
+        assert_eq!(ack.encode(), [0x03, 0x02]);
+        assert_eq!(CommandAck::decode(&[0x03, 0x02]), Ok(ack));
+        assert_eq!(
+            CommandAck::decode(&[0x03, 0x09]),
+            Err(DecodeError::UnsupportedCommandResult(0x09))
+        );
+        assert_eq!(CommandAck::decode(&[0x03]), Err(DecodeError::TooShort));
+    }
+}
@@ -1,6 +1,8 @@
 //! # Device Configuration
 //!
-//! The device configuration is read from EEPROM.
+//! The device configuration is read from EEPROM. It is stored in two slots
+//! (see [`config_slots`](crate::config_slots)), the memory map below describes
+//! a single slot, starting at 0x0808_0000 for slot A.
 //!
 //! ## Memory Map
 //!
@@ -23,30 +25,67 @@
 //!             +-----------+-----------+-----------+-----------+
 //! 0x0808_0028 | WakeupInterval        | ITempHumi | IVoltage  |
 //!             +-----------+-----------+-----------+-----------+
+//! 0x0808_002C | BatchSize | SensorEn  | IWaterTmp | WaterRes  | (only V2)
+//!             +-----------+-----------+-----------+-----------+
+//! 0x0808_0030 | ActMode   | Reserved                          | (only V2)
+//!             +-----------+-----------+-----------+-----------+
+//! 0x0808_0034 | DevEUI                                        |
+//! 0x0808_0038 |                                               |
+//!             +-----------+-----------+-----------+-----------+
+//! 0x0808_003C | JoinEUI                                       |
+//! 0x0808_0040 |                                               |
+//!             +-----------+-----------+-----------+-----------+
+//! 0x0808_0044 |                                               |
+//! 0x0808_0048 | AppKey                                        |
+//! 0x0808_004C |                                               |
+//! 0x0808_0050 |                                               |
+//!             +-----------+-----------+-----------+-----------+
+//! 0x0808_0054 | DataRate  | TxPower   | ADR       | ConfirmN  | (only V2)
+//!             +-----------+-----------+-----------+-----------+
+//! 0x0808_0058 | WaterTempOffset1      | ShtTempOffset         | (only V2)
+//!             +-----------+-----------+-----------+-----------+
+//! 0x0808_005C | ShtRhOffset           | SupplyGain            | (only V2)
+//!             +-----------+-----------+-----------+-----------+
+//! 0x0808_0060 | Probe1                                        | (only V2)
+//! 0x0808_0064 |                       | Probe2                |
+//! 0x0808_0068 |                                               |
+//!             +-----------+-----------+-----------+-----------+
+//! 0x0808_006C | Probe3                                        | (only V2)
+//! 0x0808_0070 |                       | Probe4                |
+//! 0x0808_0074 |                                               |
+//!             +-----------+-----------+-----------+-----------+
+//! 0x0808_0078 | WaterTempOffset2      | WaterTempOffset3      | (only V2)
+//!             +-----------+-----------+-----------+-----------+
+//! 0x0808_007C | WaterTempOffset4      | Reserved              | (only V2)
+//!             +-----------+-----------+-----------+-----------+
+//! 0x0808_0080 | CRC-32                                        | (only V2)
+//!             +-----------+-----------+-----------+-----------+
 //! ```
 //!
 //! ## Fields
 //!
 //! ### Header (0x0808_0000 - 0x0808_0004, 4 bytes)
 //!
-//! - `Version`: The constant `0x01`, can be used to change the config layout
-//!   in the future (1 byte)
-//! - The other three bytes are reserved, for version 1 they should contain the
-//!   sequence `0x23 0x42 0x99` (in order to have some more checks against
+//! - `Version`: The config format version, `0x01` or `0x02` (1 byte)
+//! - The other three bytes are reserved, they should contain the sequence
+//!   `0x23 0x42 0x99` (in order to have some more checks against
 //!   configuration data corruption).
 //!
-//! ### LoRaWAN Configuration (0x0808_0004 - 0x0808_0028, 36 bytes)
+//! ### LoRaWAN ABP Configuration (0x0808_0004 - 0x0808_0028, 36 bytes)
 //!
 //! - `DevAddr`: LoRaWAN device address (4 bytes)
 //! - `NwkSKey`: LoRaWAN ABP network session key (16 bytes)
 //! - `AppSKey`: LoRaWAN ABP app session key (16 bytes)
 //!
+//! These fields are only used with activation by personalization (ABP),
+//! otherwise they should be set to 0.
+//!
 //! ### Interval Configuration (0x0808_0028 - 0x0808_002C, 4 bytes)
 //!
 //! - `WakeupInterval`: How often (in seconds) the device should wake up to
 //!   start measurement(s) (2 bytes, u16, LE)
-//! - `ITempHumi`: Every n-th measurement will measure and send temperature and
-//!   humidity (1 byte, u8)
+//! - `ITempHumi`: Every n-th measurement will measure and send the SHTC3
+//!   temperature and humidity (1 byte, u8)
 //! - `IVoltage`: Every n-th measurement will measure and send battery
 //!   voltage (1 byte, u8)
 //!
@@ -60,25 +99,187 @@
 //!
 //! ...the temperature and humidity will be sent every 15 minutes, while the
 //! voltage will be sent every hour.
+//!
+//! The interval of the water temperature is stored in the uplink
+//! configuration (see below).
+//!
+//! ### Uplink Configuration (0x0808_002C - 0x0808_0030, 4 bytes, only V2)
+//!
+//! - `BatchSize`: Number of measurements to collect before sending them
+//!   together in a single batch uplink. The values 0 and 1 disable batching,
+//!   every measurement is sent immediately. (1 byte, u8)
+//! - `SensorEn`: Bitmask of the enabled sensors (1 byte)
+//!   - Bit 0: DS18B20 water temperature
+//!   - Bit 1: SHTC3 temperature and humidity
+//!   - Bit 2: Supply voltage
+//! - `IWaterTmp`: Every n-th measurement will measure and send the water
+//!   temperature (1 byte, u8)
+//! - `WaterRes`: Resolution of the DS18B20 in bits, from 9 (0.5 °C, 94 ms
+//!   conversion time) to 12 (0.0625 °C, 750 ms conversion time). The value 0
+//!   selects the default resolution of 12 bits. (1 byte, u8)
+//!
+//! Disabled sensors are never measured, regardless of their interval. The
+//! interval of a disabled sensor may be 0.
+//!
+//! Version 1 configurations end before the uplink configuration, the bytes
+//! following them are ignored. They disable batching, enable all sensors,
+//! measure the water temperature with the same interval as the SHTC3 and use
+//! a resolution of 12 bits.
+//!
+//! ### LoRaWAN OTAA Configuration (0x0808_0030 - 0x0808_0054, 36 bytes, only V2)
+//!
+//! - `ActMode`: Activation mode, `0x00` for activation by personalization
+//!   (ABP) or `0x01` for over-the-air activation (OTAA) (1 byte)
+//! - The other three bytes are reserved and should be set to 0.
+//! - `DevEUI`: LoRaWAN device EUI (8 bytes)
+//! - `JoinEUI`: LoRaWAN join EUI, also known as AppEUI (8 bytes)
+//! - `AppKey`: LoRaWAN OTAA app key (16 bytes)
+//!
+//! The EUI and key fields are only used with OTAA, otherwise they should be
+//! set to 0.
+//!
+//! ### Radio Configuration (0x0808_0054 - 0x0808_0058, 4 bytes, only V2)
+//!
+//! - `DataRate`: Initial LoRaWAN data rate, from 0 (SF12) to 5 (SF7) (1 byte, u8)
+//! - `TxPower`: RN2483 transmit power index, from 1 (14 dBm) to 5 (2 dBm)
+//!   (1 byte, u8)
+//! - `ADR`: Adaptive data rate, `0x00` for disabled or `0x01` for enabled
+//!   (1 byte)
+//! - `ConfirmN`: Every n-th measurement uplink will be sent as confirmed
+//!   uplink. The value 0 disables confirmed uplinks. (1 byte, u8)
+//!
+//! The radio configuration is applied on cold boot and after a new session
+//! has been set up. While in standby, the settings of the current session are
+//! kept (e.g. the data rate chosen by ADR).
+//!
+//! ### Calibration (0x0808_0058 - 0x0808_0060, 8 bytes, only V2)
+//!
+//! - `WaterTempOffset1`: Offset added to the water temperature of the first
+//!   DS18B20 probe, in 0.01 °C (2 bytes, i16, LE)
+//! - `ShtTempOffset`: Offset added to the SHTC3 temperature, in 0.01 °C
+//!   (2 bytes, i16, LE)
+//! - `ShtRhOffset`: Offset added to the SHTC3 relative humidity, in 0.01 %RH
+//!   (2 bytes, i16, LE)
+//! - `SupplyGain`: Gain applied to the supply voltage, in 1/10000, from 5000
+//!   (0.5) to 15000 (1.5) (2 bytes, u16, LE)
+//!
+//! The calibration is applied on the device, before the values are encoded.
+//! The offsets of the other probes follow the probe order (see below).
+//!
+//! ### Probe Order (0x0808_0060 - 0x0808_0078, 24 bytes, only V2)
+//!
+//! - `Probe1` to `Probe4`: 48 bit serial numbers of the DS18B20 probes, in
+//!   the order in which their temperatures are sent (6 bytes each, LE). The
+//!   serial number is the part of the 64 bit ROM address between family code
+//!   and CRC. The value 0 marks an unused position.
+//!
+//! Probes which are not listed are assigned to the unused positions, ordered
+//! by serial number. Without a probe order, all probes are ordered by serial
+//! number.
+//!
+//! ### Probe Calibration (0x0808_0078 - 0x0808_0080, 8 bytes, only V2)
+//!
+//! - `WaterTempOffset2` to `WaterTempOffset4`: Offsets added to the water
+//!   temperatures of the second to fourth DS18B20 probe, in 0.01 °C (2 bytes
+//!   each, i16, LE)
+//! - The last two bytes are reserved and should be set to 0.
+//!
+//! The n-th offset applies to the n-th water temperature in the uplink, i.e.
+//! to the probe at the n-th position of the probe order.
+//!
+//! ### Checksum (0x0808_0080 - 0x0808_0084, 4 bytes, only V2)
+//!
+//! - `CRC-32`: CRC-32 (IEEE 802.3, as used by zlib) over all preceding bytes
+//!   of the configuration block (4 bytes, u32, LE)
+//!
+//! Version 1 configurations (44 bytes) do not contain the uplink
+//! configuration, the OTAA configuration, the radio configuration, the
+//! calibration, the probe order, the probe calibration and the checksum. They always use ABP, the
+//! default radio configuration (data rate 4, TX power index 1, no ADR, no
+//! confirmed uplinks), no calibration and no probe order. They can still be
+//! read, but are always written as version 2.
 
 use core::{convert::TryInto, fmt};
 
-pub const BASE_ADDR: usize = 0x0808_0000;
-pub const CONFIG_DATA_SIZE: usize = 44;
+use crate::{
+    calibration::Calibration,
+    measurement::{max_batch_size, max_payload_len},
+    memory_map,
+};
+
+pub const BASE_ADDR: usize = memory_map::CONFIG_SLOTS.start;
+pub const CONFIG_DATA_SIZE: usize = 132;
+
+/// Size of a version 1 configuration block (without checksum).
+const CONFIG_DATA_SIZE_V1: usize = 44;
+
+/// Offset of the CRC-32 in a version 2 configuration block.
+const CHECKSUM_OFFSET: usize = 0x80;
+
+/// Offset of the probe order in a version 2 configuration block.
+const PROBE_ORDER_OFFSET: usize = 0x60;
+
+/// Length of a probe serial number in the configuration block.
+const PROBE_SERIAL_LEN: usize = 6;
+
+/// Offsets of the per-probe water temperature offsets in a version 2
+/// configuration block, the first one is part of the calibration.
+const WATER_TEMP_OFFSET_OFFSETS: [usize; MAX_PROBES] = [0x58, 0x78, 0x7A, 0x7C];
+
+/// Maximum number of DS18B20 probes on the one-wire bus.
+pub const MAX_PROBES: usize = 4;
+
+/// Largest 48 bit probe serial number.
+pub const MAX_PROBE_SERIAL: u64 = 0xFFFF_FFFF_FFFF;
+
+/// Default LoRaWAN data rate (SF8).
+pub const DEFAULT_DATA_RATE: u8 = 4;
+
+/// Highest supported LoRaWAN data rate (SF7).
+pub const MAX_DATA_RATE: u8 = 5;
+
+/// Default RN2483 transmit power index (14 dBm).
+pub const DEFAULT_TX_POWER_INDEX: u8 = 1;
+
+/// Supported RN2483 transmit power indices (14 dBm down to 2 dBm).
+pub const TX_POWER_INDEX_RANGE: core::ops::RangeInclusive<u8> = 1..=5;
+
+/// Default DS18B20 resolution (12 bits, the power-on default of the DS18B20).
+pub const DEFAULT_WATER_TEMP_RESOLUTION: Ds18b20Resolution = Ds18b20Resolution::Bits12;
+
+/// Supported supply voltage gains (0.5 to 1.5).
+pub const SUPPLY_GAIN_RANGE: core::ops::RangeInclusive<u16> = 5_000..=15_000;
 
 #[derive(PartialEq, Debug, Copy, Clone)]
 #[cfg_attr(feature = "serde", derive(serde_repr::Deserialize_repr))]
 #[repr(u8)]
 pub enum ConfigVersion {
     V1 = 1,
+    V2 = 2,
 }
 
 impl fmt::Display for ConfigVersion {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::V1 => write!(f, "1"),
+            Self::V2 => write!(f, "2"),
+        }
+    }
+}
+
+/// Calculate the CRC-32 (IEEE 802.3) of the given data.
+///
+/// This is the bitwise implementation, which does not need a lookup table.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFF_u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
         }
     }
+    !crc
 }
 
 #[derive(Debug, PartialEq)]
@@ -89,6 +290,20 @@ pub enum ConfigError {
     UnsupportedVersion(u8),
     /// Wrong magic bytes, the configuration data might be corrupted.
     WrongMagicBytes,
+    /// The checksum does not match, the configuration data is corrupted.
+    ChecksumMismatch,
+    /// The interval with the given name is 0.
+    ZeroInterval(&'static str),
+    /// The device address is all zeroes.
+    ZeroDevAddr,
+    /// The key with the given name is all zeroes.
+    ZeroKey(&'static str),
+    /// The DevEUI is all zeroes.
+    ZeroDevEui,
+    /// The activation mode byte is not supported.
+    UnsupportedActivationMode(u8),
+    /// The value of the field with the given name is out of range.
+    OutOfRange(&'static str),
 }
 
 impl fmt::Display for ConfigError {
@@ -97,48 +312,377 @@ impl fmt::Display for ConfigError {
             Self::UnsupportedVersion(v) => write!(f, "Unsupported config format version ({})", v),
             Self::WrongMagicBytes => write!(f, "Wrong magic bytes"),
             Self::WrongSliceLength => write!(f, "Wrong slice length"),
+            Self::ChecksumMismatch => write!(f, "Checksum mismatch"),
+            Self::ZeroInterval(name) => write!(f, "Interval {} must not be 0", name),
+            Self::ZeroDevAddr => write!(f, "Device address must not be all zeroes"),
+            Self::ZeroKey(name) => write!(f, "Key {} must not be all zeroes", name),
+            Self::ZeroDevEui => write!(f, "DevEUI must not be all zeroes"),
+            Self::UnsupportedActivationMode(mode) => {
+                write!(f, "Unsupported activation mode ({})", mode)
+            }
+            Self::OutOfRange(name) => write!(f, "Value of {} is out of range", name),
         }
     }
 }
 
-#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
-#[derive(Debug, PartialEq)]
+/// LoRaWAN activation mode, including the corresponding credentials.
+///
+/// In the TOML config, the mode is selected by the `activation_mode` field
+/// (`"abp"` or `"otaa"`). Configs written before OTAA was supported do not
+/// contain this field, they use ABP.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Activation {
+    /// Activation by personalization
+    Abp {
+        /// LoRaWAN device address (4 bytes)
+        devaddr: [u8; 4],
+        /// LoRaWAN ABP network session key (16 bytes)
+        nwkskey: [u8; 16],
+        /// LoRaWAN ABP app session key (16 bytes)
+        appskey: [u8; 16],
+    },
+    /// Over-the-air activation
+    Otaa {
+        /// LoRaWAN device EUI (8 bytes)
+        deveui: [u8; 8],
+        /// LoRaWAN join EUI (8 bytes)
+        joineui: [u8; 8],
+        /// LoRaWAN OTAA app key (16 bytes)
+        appkey: [u8; 16],
+    },
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Activation {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::de::Error;
+
+        #[derive(serde::Deserialize)]
+        #[serde(rename_all = "lowercase")]
+        enum ActivationMode {
+            Abp,
+            Otaa,
+        }
+
+        /// The activation fields of the TOML config. Only the credentials of
+        /// the selected mode are required.
+        #[derive(serde::Deserialize)]
+        struct ActivationFields {
+            activation_mode: Option<ActivationMode>,
+            #[serde(default, deserialize_with = "deserialize_hex_option")]
+            devaddr: Option<[u8; 4]>,
+            #[serde(default, deserialize_with = "deserialize_hex_option")]
+            nwkskey: Option<[u8; 16]>,
+            #[serde(default, deserialize_with = "deserialize_hex_option")]
+            appskey: Option<[u8; 16]>,
+            #[serde(default, deserialize_with = "deserialize_hex_option")]
+            deveui: Option<[u8; 8]>,
+            #[serde(default, deserialize_with = "deserialize_hex_option")]
+            joineui: Option<[u8; 8]>,
+            #[serde(default, deserialize_with = "deserialize_hex_option")]
+            appkey: Option<[u8; 16]>,
+        }
+
+        let fields = ActivationFields::deserialize(deserializer)?;
+        match fields.activation_mode.unwrap_or(ActivationMode::Abp) {
+            ActivationMode::Abp => Ok(Self::Abp {
+                devaddr: fields
+                    .devaddr
+                    .ok_or_else(|| D::Error::missing_field("devaddr"))?,
+                nwkskey: fields
+                    .nwkskey
+                    .ok_or_else(|| D::Error::missing_field("nwkskey"))?,
+                appskey: fields
+                    .appskey
+                    .ok_or_else(|| D::Error::missing_field("appskey"))?,
+            }),
+            ActivationMode::Otaa => Ok(Self::Otaa {
+                deveui: fields
+                    .deveui
+                    .ok_or_else(|| D::Error::missing_field("deveui"))?,
+                joineui: fields
+                    .joineui
+                    .ok_or_else(|| D::Error::missing_field("joineui"))?,
+                appkey: fields
+                    .appkey
+                    .ok_or_else(|| D::Error::missing_field("appkey"))?,
+            }),
+        }
+    }
+}
+
+/// Deserialize an optional hex string, a missing field is handled by
+/// `#[serde(default)]`.
+#[cfg(feature = "serde")]
+fn deserialize_hex_option<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: hex::FromHex,
+    T::Error: fmt::Display,
+{
+    hex::serde::deserialize(deserializer).map(Some)
+}
+
+/// Resolution of the DS18B20 temperature conversion.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde_repr::Deserialize_repr))]
+#[repr(u8)]
+pub enum Ds18b20Resolution {
+    /// 9 bits (0.5 °C)
+    Bits9 = 9,
+    /// 10 bits (0.25 °C)
+    Bits10 = 10,
+    /// 11 bits (0.125 °C)
+    Bits11 = 11,
+    /// 12 bits (0.0625 °C), the power-on default of the DS18B20
+    Bits12 = 12,
+}
+
+impl Ds18b20Resolution {
+    /// Return the resolution with the given number of bits.
+    pub fn from_bits(bits: u8) -> Option<Self> {
+        match bits {
+            9 => Some(Self::Bits9),
+            10 => Some(Self::Bits10),
+            11 => Some(Self::Bits11),
+            12 => Some(Self::Bits12),
+            _ => None,
+        }
+    }
+
+    /// Return the resolution encoded in the DS18B20 configuration register
+    /// (bits R1 and R0, the other bits are ignored).
+    pub fn from_config_register(register: u8) -> Self {
+        match (register >> 5) & 0b11 {
+            0b00 => Self::Bits9,
+            0b01 => Self::Bits10,
+            0b10 => Self::Bits11,
+            _ => Self::Bits12,
+        }
+    }
+
+    /// Return the value of the DS18B20 configuration register for this
+    /// resolution. The unused bits are set like the power-on default.
+    pub const fn config_register(self) -> u8 {
+        ((self as u8 - 9) << 5) | 0x1F
+    }
+
+    /// Return the maximum conversion time in milliseconds (rounded up).
+    pub const fn conversion_time_ms(self) -> u32 {
+        match self {
+            Self::Bits9 => 94,
+            Self::Bits10 => 188,
+            Self::Bits11 => 375,
+            Self::Bits12 => 750,
+        }
+    }
+
+    /// Clear the undefined low bits of a raw temperature (in 1/16 °C).
+    ///
+    /// At a resolution below 12 bits, the least significant bits of the
+    /// temperature register are undefined.
+    pub const fn mask_raw(self, raw: i16) -> i16 {
+        raw & !((1 << (12 - self as u8)) - 1)
+    }
+}
+
+/// A sensor that can be enabled and measured at its own interval.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Sensor {
+    /// DS18B20 water temperature
+    WaterTemp,
+    /// SHTC3 temperature and humidity
+    TempHumi,
+    /// Supply voltage
+    Voltage,
+}
+
+impl Sensor {
+    /// All sensors, in the order of their enable bits.
+    pub const ALL: [Sensor; 3] = [Sensor::WaterTemp, Sensor::TempHumi, Sensor::Voltage];
+
+    /// Return the bit of this sensor in the `SensorEn` bitmask.
+    pub const fn mask_bit(self) -> u8 {
+        match self {
+            Sensor::WaterTemp => 1 << 0,
+            Sensor::TempHumi => 1 << 1,
+            Sensor::Voltage => 1 << 2,
+        }
+    }
+
+    /// Return the name of the interval config field of this sensor.
+    pub const fn interval_name(self) -> &'static str {
+        match self {
+            Sensor::WaterTemp => "nth_water_temp",
+            Sensor::TempHumi => "nth_temp_humi",
+            Sensor::Voltage => "nth_voltage",
+        }
+    }
+}
+
+/// Device configuration.
+///
+/// When deserialized with serde, the configuration is validated (see
+/// [`Config::validate`]).
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(remote = "Self"))]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Config {
     /// Configuration format version
     pub version: ConfigVersion,
-    /// LoRaWAN device address (4 bytes)
-    #[cfg_attr(feature = "serde", serde(deserialize_with = "hex::serde::deserialize"))]
-    pub devaddr: [u8; 4],
-    /// LoRaWAN ABP network session key (16 bytes)
-    #[cfg_attr(feature = "serde", serde(deserialize_with = "hex::serde::deserialize"))]
-    pub nwkskey: [u8; 16],
-    /// LoRaWAN ABP app session key (16 bytes)
-    #[cfg_attr(feature = "serde", serde(deserialize_with = "hex::serde::deserialize"))]
-    pub appskey: [u8; 16],
+    /// LoRaWAN activation mode and credentials
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub activation: Activation,
     /// How often (in seconds) the device should wake up to start measurement(s)
     pub wakeup_interval_seconds: u16,
     /// Every n-th measurement will measure and send temperature and humidity
     pub nth_temp_humi: u8,
     /// Every n-th measurement will measure and send battery voltage
     pub nth_voltage: u8,
+    /// Every n-th measurement will measure and send water temperature
+    #[cfg_attr(feature = "serde", serde(default = "default_nth"))]
+    pub nth_water_temp: u8,
+    /// Whether the DS18B20 water temperature sensor is enabled
+    #[cfg_attr(feature = "serde", serde(default = "default_enabled"))]
+    pub enable_water_temp: bool,
+    /// Resolution of the DS18B20 water temperature sensor (in bits)
+    #[cfg_attr(feature = "serde", serde(default = "default_water_temp_resolution"))]
+    pub water_temp_resolution: Ds18b20Resolution,
+    /// Whether the SHTC3 temperature and humidity sensor is enabled
+    #[cfg_attr(feature = "serde", serde(default = "default_enabled"))]
+    pub enable_temp_humi: bool,
+    /// Whether the supply voltage is measured
+    #[cfg_attr(feature = "serde", serde(default = "default_enabled"))]
+    pub enable_voltage: bool,
+    /// Number of measurements to send together in a single batch uplink (0
+    /// and 1 disable batching)
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub batch_size: u8,
+    /// Initial LoRaWAN data rate (0-5, corresponding to SF12-SF7)
+    #[cfg_attr(feature = "serde", serde(default = "default_data_rate"))]
+    pub data_rate: u8,
+    /// RN2483 transmit power index (1-5, corresponding to 14-2 dBm)
+    #[cfg_attr(feature = "serde", serde(default = "default_tx_power_index"))]
+    pub tx_power_index: u8,
+    /// Whether adaptive data rate (ADR) is enabled
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub adr: bool,
+    /// Every n-th measurement uplink is sent as confirmed uplink (0 disables
+    /// confirmed uplinks)
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub confirmed_every_n: u8,
+    /// Sensor calibration
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub calibration: Calibration,
+    /// Serial numbers of the DS18B20 probes, in the order in which their
+    /// temperatures are sent (0 marks an unused position)
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, deserialize_with = "deserialize_per_probe")
+    )]
+    pub probe_order: [u64; MAX_PROBES],
+}
+
+/// Deserialize the configuration with the derived implementation (generated
+/// as inherent function by `serde(remote = "Self")`), then validate it.
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Config {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let config = Config::deserialize(deserializer)?;
+        config.validate().map_err(serde::de::Error::custom)?;
+        Ok(config)
+    }
+}
+
+#[cfg(feature = "serde")]
+fn default_nth() -> u8 {
+    1
+}
+
+#[cfg(feature = "serde")]
+fn default_enabled() -> bool {
+    true
+}
+
+#[cfg(feature = "serde")]
+fn default_water_temp_resolution() -> Ds18b20Resolution {
+    DEFAULT_WATER_TEMP_RESOLUTION
+}
+
+#[cfg(feature = "serde")]
+fn default_data_rate() -> u8 {
+    DEFAULT_DATA_RATE
+}
+
+#[cfg(feature = "serde")]
+fn default_tx_power_index() -> u8 {
+    DEFAULT_TX_POWER_INDEX
+}
+
+/// Deserialize a list of up to `MAX_PROBES` values (one per probe position),
+/// unused positions are filled with the default value.
+#[cfg(feature = "serde")]
+pub(crate) fn deserialize_per_probe<'de, D, T>(deserializer: D) -> Result<[T; MAX_PROBES], D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: serde::Deserialize<'de> + Default + Copy,
+{
+    struct PerProbeVisitor<T>(core::marker::PhantomData<T>);
+
+    impl<'de, T> serde::de::Visitor<'de> for PerProbeVisitor<T>
+    where
+        T: serde::Deserialize<'de> + Default + Copy,
+    {
+        type Value = [T; MAX_PROBES];
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "a list of at most {} values, one per probe", MAX_PROBES)
+        }
+
+        fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+        where
+            A: serde::de::SeqAccess<'de>,
+        {
+            let mut values = [T::default(); MAX_PROBES];
+            let mut count = 0;
+            while let Some(value) = seq.next_element()? {
+                if count == MAX_PROBES {
+                    return Err(serde::de::Error::invalid_length(count + 1, &self));
+                }
+                values[count] = value;
+                count += 1;
+            }
+            Ok(values)
+        }
+    }
+
+    deserializer.deserialize_seq(PerProbeVisitor(core::marker::PhantomData))
 }
 
 impl Config {
     /// Read current device configuration from a slice.
     ///
     /// Returns an error if the version field does not contain a supported
-    /// value, or if the slice length is less than `CONFIG_DATA_SIZE`.
+    /// value, if the slice is too short for the config version, if the
+    /// checksum of a version 2 config does not match, or if the config is
+    /// not valid (see [`validate`](Self::validate)).
     ///
     /// TODO: Fuzz this!
     pub fn from_slice(slice: &[u8]) -> Result<Self, ConfigError> {
         // Validate slice length
-        if slice.len() < CONFIG_DATA_SIZE {
+        if slice.len() < CONFIG_DATA_SIZE_V1 {
             return Err(ConfigError::WrongSliceLength);
         }
 
         // Determine version
         let version: ConfigVersion = match slice[0] {
             1 => ConfigVersion::V1,
+            2 => ConfigVersion::V2,
             other => return Err(ConfigError::UnsupportedVersion(other)),
         };
 
@@ -147,16 +691,51 @@ impl Config {
             return Err(ConfigError::WrongMagicBytes);
         }
 
-        // Read keys
-        let devaddr: [u8; 4] = slice[0x04..=0x07]
-            .try_into()
-            .expect("Reading devaddr failed");
-        let nwkskey: [u8; 16] = slice[0x08..=0x17]
-            .try_into()
-            .expect("Reading nwkskey failed");
-        let appskey: [u8; 16] = slice[0x18..=0x27]
-            .try_into()
-            .expect("Reading appskey failed");
+        // Validate checksum
+        if version == ConfigVersion::V2 {
+            if slice.len() < CONFIG_DATA_SIZE {
+                return Err(ConfigError::WrongSliceLength);
+            }
+            let checksum = u32::from_le_bytes(
+                slice[CHECKSUM_OFFSET..CHECKSUM_OFFSET + 4]
+                    .try_into()
+                    .expect("Reading checksum failed"),
+            );
+            if crc32(&slice[..CHECKSUM_OFFSET]) != checksum {
+                return Err(ConfigError::ChecksumMismatch);
+            }
+        }
+
+        // Read activation mode and keys
+        let activation = match version {
+            ConfigVersion::V1 => 0x00,
+            ConfigVersion::V2 => slice[0x30],
+        };
+        let activation = match activation {
+            0x00 => Activation::Abp {
+                devaddr: slice[0x04..=0x07]
+                    .try_into()
+                    .expect("Reading devaddr failed"),
+                nwkskey: slice[0x08..=0x17]
+                    .try_into()
+                    .expect("Reading nwkskey failed"),
+                appskey: slice[0x18..=0x27]
+                    .try_into()
+                    .expect("Reading appskey failed"),
+            },
+            0x01 => Activation::Otaa {
+                deveui: slice[0x34..=0x3B]
+                    .try_into()
+                    .expect("Reading deveui failed"),
+                joineui: slice[0x3C..=0x43]
+                    .try_into()
+                    .expect("Reading joineui failed"),
+                appkey: slice[0x44..=0x53]
+                    .try_into()
+                    .expect("Reading appkey failed"),
+            },
+            other => return Err(ConfigError::UnsupportedActivationMode(other)),
+        };
 
         // Read interval data
         let wakeup_interval_seconds = u16::from_le_bytes(
@@ -167,58 +746,349 @@ impl Config {
         let nth_temp_humi = slice[0x2A];
         let nth_voltage = slice[0x2B];
 
-        Ok(Self {
+        // Read uplink config
+        let (batch_size, sensor_enable, nth_water_temp) = match version {
+            ConfigVersion::V1 => (0, 0xFF, nth_temp_humi),
+            ConfigVersion::V2 => (slice[0x2C], slice[0x2D], slice[0x2E]),
+        };
+        let water_temp_resolution = match version {
+            ConfigVersion::V1 => DEFAULT_WATER_TEMP_RESOLUTION,
+            ConfigVersion::V2 => match slice[0x2F] {
+                0 => DEFAULT_WATER_TEMP_RESOLUTION,
+                bits => Ds18b20Resolution::from_bits(bits)
+                    .ok_or(ConfigError::OutOfRange("water_temp_resolution"))?,
+            },
+        };
+
+        // Read radio config
+        let (data_rate, tx_power_index, adr, confirmed_every_n) = match version {
+            ConfigVersion::V1 => (DEFAULT_DATA_RATE, DEFAULT_TX_POWER_INDEX, false, 0),
+            ConfigVersion::V2 => (slice[0x54], slice[0x55], slice[0x56] != 0, slice[0x57]),
+        };
+
+        // Read probe order
+        let mut probe_order = [0; MAX_PROBES];
+        if version == ConfigVersion::V2 {
+            for (i, serial) in probe_order.iter_mut().enumerate() {
+                let offset = PROBE_ORDER_OFFSET + i * PROBE_SERIAL_LEN;
+                let mut bytes = [0; 8];
+                bytes[..PROBE_SERIAL_LEN]
+                    .copy_from_slice(&slice[offset..offset + PROBE_SERIAL_LEN]);
+                *serial = u64::from_le_bytes(bytes);
+            }
+        }
+
+        // Read calibration
+        let calibration = match version {
+            ConfigVersion::V1 => Calibration::default(),
+            ConfigVersion::V2 => {
+                let read_u16 = |offset: usize| {
+                    u16::from_le_bytes(
+                        slice[offset..offset + 2]
+                            .try_into()
+                            .expect("Reading calibration failed"),
+                    )
+                };
+                let mut water_temp_offsets = [0; MAX_PROBES];
+                for (offset, &addr) in water_temp_offsets
+                    .iter_mut()
+                    .zip(WATER_TEMP_OFFSET_OFFSETS.iter())
+                {
+                    *offset = read_u16(addr) as i16;
+                }
+                Calibration {
+                    water_temp_offsets,
+                    sht_temp_offset: read_u16(0x5A) as i16,
+                    sht_rh_offset: read_u16(0x5C) as i16,
+                    supply_gain: read_u16(0x5E),
+                }
+            }
+        };
+
+        let config = Self {
             version,
-            devaddr,
-            nwkskey,
-            appskey,
+            activation,
             wakeup_interval_seconds,
             nth_temp_humi,
             nth_voltage,
-        })
+            nth_water_temp,
+            enable_water_temp: sensor_enable & Sensor::WaterTemp.mask_bit() != 0,
+            water_temp_resolution,
+            enable_temp_humi: sensor_enable & Sensor::TempHumi.mask_bit() != 0,
+            enable_voltage: sensor_enable & Sensor::Voltage.mask_bit() != 0,
+            batch_size,
+            data_rate,
+            tx_power_index,
+            adr,
+            confirmed_every_n,
+            calibration,
+            probe_order,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Validate the configuration values.
+    ///
+    /// Intervals of enabled sensors must not be 0 (they are used as divisors),
+    /// and the device
+    /// address, DevEUI and keys must not be all zeroes (the erased state of
+    /// the EEPROM). The JoinEUI may be all zeroes. The data rate and transmit
+    /// power index must be supported by the RN2483, and a full batch must fit
+    /// into a single uplink at the configured data rate. The supply voltage
+    /// gain must be in [`SUPPLY_GAIN_RANGE`], and the probe serial numbers
+    /// must fit into 48 bits.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.wakeup_interval_seconds == 0 {
+            return Err(ConfigError::ZeroInterval("wakeup_interval_seconds"));
+        }
+        for sensor in Sensor::ALL {
+            if self.is_enabled(sensor) && self.nth(sensor) == 0 {
+                return Err(ConfigError::ZeroInterval(sensor.interval_name()));
+            }
+        }
+        if self.data_rate > MAX_DATA_RATE {
+            return Err(ConfigError::OutOfRange("data_rate"));
+        }
+        if self.batch_size as usize > max_batch_size(max_payload_len(self.data_rate)) {
+            return Err(ConfigError::OutOfRange("batch_size"));
+        }
+        if !TX_POWER_INDEX_RANGE.contains(&self.tx_power_index) {
+            return Err(ConfigError::OutOfRange("tx_power_index"));
+        }
+        if !SUPPLY_GAIN_RANGE.contains(&self.calibration.supply_gain) {
+            return Err(ConfigError::OutOfRange("supply_gain"));
+        }
+        if self
+            .probe_order
+            .iter()
+            .any(|serial| *serial > MAX_PROBE_SERIAL)
+        {
+            return Err(ConfigError::OutOfRange("probe_order"));
+        }
+        match self.activation {
+            Activation::Abp {
+                devaddr,
+                nwkskey,
+                appskey,
+            } => {
+                if devaddr == [0; 4] {
+                    return Err(ConfigError::ZeroDevAddr);
+                }
+                if nwkskey == [0; 16] {
+                    return Err(ConfigError::ZeroKey("nwkskey"));
+                }
+                if appskey == [0; 16] {
+                    return Err(ConfigError::ZeroKey("appskey"));
+                }
+            }
+            Activation::Otaa { deveui, appkey, .. } => {
+                if deveui == [0; 8] {
+                    return Err(ConfigError::ZeroDevEui);
+                }
+                if appkey == [0; 16] {
+                    return Err(ConfigError::ZeroKey("appkey"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Return whether the given sensor is enabled.
+    pub fn is_enabled(&self, sensor: Sensor) -> bool {
+        match sensor {
+            Sensor::WaterTemp => self.enable_water_temp,
+            Sensor::TempHumi => self.enable_temp_humi,
+            Sensor::Voltage => self.enable_voltage,
+        }
+    }
+
+    /// Return the measurement interval of the given sensor (every n-th
+    /// wakeup).
+    pub fn nth(&self, sensor: Sensor) -> u8 {
+        match sensor {
+            Sensor::WaterTemp => self.nth_water_temp,
+            Sensor::TempHumi => self.nth_temp_humi,
+            Sensor::Voltage => self.nth_voltage,
+        }
+    }
+
+    /// Return whether the given sensor should be measured in the given wakeup
+    /// cycle.
+    pub fn should_measure(&self, sensor: Sensor, wakeup_cycle: u32) -> bool {
+        // An interval of 0 never matches
+        self.is_enabled(sensor) && wakeup_cycle.checked_rem(u32::from(self.nth(sensor))) == Some(0)
+    }
+
+    /// Assign the DS18B20 probes with the given serial numbers to positions.
+    ///
+    /// Returns, for every position, the index of the assigned probe in
+    /// `serials`. Probes listed in the probe order keep their position, even
+    /// if a probe before them is missing. The other probes fill the unused
+    /// positions, ordered by serial number. Probes which do not fit into
+    /// `MAX_PROBES` positions are ignored.
+    pub fn probe_positions(&self, serials: &[u64]) -> [Option<usize>; MAX_PROBES] {
+        let mut positions = [None; MAX_PROBES];
+        for (position, ordered) in positions.iter_mut().zip(self.probe_order.iter()) {
+            if *ordered != 0 {
+                *position = serials.iter().position(|serial| serial == ordered);
+            }
+        }
+
+        // Fill the unused positions with the unlisted probes, smallest serial
+        // number first
+        let mut previous = None;
+        for (position, ordered) in positions.iter_mut().zip(self.probe_order.iter()) {
+            if *ordered != 0 {
+                continue;
+            }
+            let next = serials
+                .iter()
+                .enumerate()
+                .filter(|(_, serial)| !self.probe_order.contains(serial))
+                .filter(|(_, serial)| Some(**serial) > previous)
+                .min_by_key(|(_, serial)| **serial);
+            *position = next.map(|(index, _)| index);
+            previous = next.map(|(_, serial)| *serial);
+            if next.is_none() {
+                break;
+            }
+        }
+        positions
+    }
+
+    /// Return the `SensorEn` bitmask of the enabled sensors.
+    fn sensor_enable_mask(&self) -> u8 {
+        Sensor::ALL
+            .iter()
+            .filter(|sensor| self.is_enabled(**sensor))
+            .fold(0, |mask, sensor| mask | sensor.mask_bit())
     }
 
     /// Serialize the configuration into the in-memory representation.
+    ///
+    /// The configuration is always serialized in the latest format version
+    /// (V2), regardless of the `version` field.
     pub fn serialize(&self) -> [u8; CONFIG_DATA_SIZE] {
         let mut data = [0; CONFIG_DATA_SIZE];
 
         // Write version
-        data[0] = 1;
+        data[0] = ConfigVersion::V2 as u8;
 
         // Write magic bytes
         data[1] = 0x23;
         data[2] = 0x42;
         data[3] = 0x99;
 
-        // Write keys
-        data[0x04..=0x07].copy_from_slice(&self.devaddr);
-        data[0x08..=0x17].copy_from_slice(&self.nwkskey);
-        data[0x18..=0x27].copy_from_slice(&self.appskey);
+        // Write activation mode and keys
+        match self.activation {
+            Activation::Abp {
+                devaddr,
+                nwkskey,
+                appskey,
+            } => {
+                data[0x04..=0x07].copy_from_slice(&devaddr);
+                data[0x08..=0x17].copy_from_slice(&nwkskey);
+                data[0x18..=0x27].copy_from_slice(&appskey);
+                data[0x30] = 0x00;
+            }
+            Activation::Otaa {
+                deveui,
+                joineui,
+                appkey,
+            } => {
+                data[0x30] = 0x01;
+                data[0x34..=0x3B].copy_from_slice(&deveui);
+                data[0x3C..=0x43].copy_from_slice(&joineui);
+                data[0x44..=0x53].copy_from_slice(&appkey);
+            }
+        }
 
         // Write config
         data[0x28..=0x29].copy_from_slice(&u16::to_le_bytes(self.wakeup_interval_seconds));
         data[0x2A] = self.nth_temp_humi;
         data[0x2B] = self.nth_voltage;
 
+        // Write uplink config
+        data[0x2C] = self.batch_size;
+        data[0x2D] = self.sensor_enable_mask();
+        data[0x2E] = self.nth_water_temp;
+        data[0x2F] = self.water_temp_resolution as u8;
+
+        // Write radio config
+        data[0x54] = self.data_rate;
+        data[0x55] = self.tx_power_index;
+        data[0x56] = self.adr as u8;
+        data[0x57] = self.confirmed_every_n;
+
+        // Write calibration
+        let calibration = &self.calibration;
+        for (offset, &addr) in calibration
+            .water_temp_offsets
+            .iter()
+            .zip(WATER_TEMP_OFFSET_OFFSETS.iter())
+        {
+            data[addr..addr + 2].copy_from_slice(&offset.to_le_bytes());
+        }
+        data[0x5A..=0x5B].copy_from_slice(&calibration.sht_temp_offset.to_le_bytes());
+        data[0x5C..=0x5D].copy_from_slice(&calibration.sht_rh_offset.to_le_bytes());
+        data[0x5E..=0x5F].copy_from_slice(&calibration.supply_gain.to_le_bytes());
+
+        // Write probe order
+        for (i, serial) in self.probe_order.iter().enumerate() {
+            let offset = PROBE_ORDER_OFFSET + i * PROBE_SERIAL_LEN;
+            data[offset..offset + PROBE_SERIAL_LEN]
+                .copy_from_slice(&serial.to_le_bytes()[..PROBE_SERIAL_LEN]);
+        }
+
+        // Write checksum
+        let checksum = crc32(&data[..CHECKSUM_OFFSET]);
+        data[CHECKSUM_OFFSET..CHECKSUM_OFFSET + 4].copy_from_slice(&checksum.to_le_bytes());
+
         data
     }
 }
 
+/// Return a valid configuration for tests.
+#[cfg(test)]
+pub(crate) fn test_config() -> Config {
+    Config {
+        version: ConfigVersion::V2,
+        activation: Activation::Abp {
+            devaddr: [0x26, 0x01, 0x1B, 0x5E],
+            nwkskey: [1; 16],
+            appskey: [2; 16],
+        },
+        wakeup_interval_seconds: 123,
+        nth_temp_humi: 1,
+        nth_voltage: 2,
+        nth_water_temp: 3,
+        enable_water_temp: true,
+        water_temp_resolution: Ds18b20Resolution::Bits10,
+        enable_temp_humi: false,
+        enable_voltage: true,
+        batch_size: 4,
+        data_rate: 3,
+        tx_power_index: 3,
+        adr: true,
+        confirmed_every_n: 10,
+        calibration: Calibration {
+            water_temp_offsets: [-15, 0, 20, 0],
+            sht_temp_offset: 30,
+            sht_rh_offset: -200,
+            supply_gain: 9_800,
+        },
+        probe_order: [0x0000_0B4A_1BFF, 0, 0x0000_0C1D_2E3F, 0],
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_roundtrip_ser_de() {
-        let config = Config {
-            version: ConfigVersion::V1,
-            devaddr: [0; 4],
-            nwkskey: [1; 16],
-            appskey: [2; 16],
-            wakeup_interval_seconds: 123,
-            nth_temp_humi: 1,
-            nth_voltage: 2,
-        };
+        let config = test_config();
 
         // Serialize
         let serialized = config.serialize();
@@ -230,10 +1100,566 @@ mod tests {
         assert_eq!(deserialized, config);
     }
 
+    #[test]
+    fn test_serialize_v1_as_v2() {
+        let config = Config {
+            version: ConfigVersion::V1,
+            ..test_config()
+        };
+        let deserialized = Config::from_slice(&config.serialize()).unwrap();
+        assert_eq!(deserialized.version, ConfigVersion::V2);
+    }
+
+    #[test]
+    fn test_read_v1() {
+        // A V1 block has no checksum
+        let mut data = test_config().serialize();
+        data[0] = 1;
+        let v1_data = &data[..CONFIG_DATA_SIZE_V1];
+
+        let config = Config::from_slice(v1_data).unwrap();
+        assert_eq!(config.version, ConfigVersion::V1);
+        assert_eq!(
+            config,
+            Config {
+                version: ConfigVersion::V1,
+                nth_water_temp: 1,
+                water_temp_resolution: Ds18b20Resolution::Bits12,
+                enable_temp_humi: true,
+                batch_size: 0,
+                data_rate: DEFAULT_DATA_RATE,
+                tx_power_index: DEFAULT_TX_POWER_INDEX,
+                adr: false,
+                confirmed_every_n: 0,
+                calibration: Calibration::default(),
+                probe_order: [0; MAX_PROBES],
+                ..test_config()
+            }
+        );
+
+        // Data following the V1 block (e.g. the erased EEPROM) is ignored
+        data[CONFIG_DATA_SIZE_V1..].fill(0xFF);
+        assert_eq!(Config::from_slice(&data).unwrap(), config);
+    }
+
+    #[test]
+    fn test_checksum_mismatch() {
+        let serialized = test_config().serialize();
+        for i in 0x04..CONFIG_DATA_SIZE {
+            let mut data = serialized;
+            data[i] ^= 0x10;
+            assert_eq!(
+                Config::from_slice(&data),
+                Err(ConfigError::ChecksumMismatch),
+                "Bit flip at offset {:#x}",
+                i
+            );
+        }
+    }
+
+    #[test]
+    fn test_v2_length_validation() {
+        let data = test_config().serialize();
+        let err = Config::from_slice(&data[..CONFIG_DATA_SIZE - 1]).unwrap_err();
+        assert_eq!(err, ConfigError::WrongSliceLength);
+    }
+
+    #[test]
+    fn test_crc32() {
+        // Check value of the CRC-32 (IEEE 802.3)
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
     #[test]
     fn test_from_slice_length_validation() {
         let data = [1, 2, 3];
         let err = Config::from_slice(&data).unwrap_err();
         assert_eq!(err, ConfigError::WrongSliceLength);
     }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(test_config().validate(), Ok(()));
+
+        let invalid = [
+            (
+                Config {
+                    wakeup_interval_seconds: 0,
+                    ..test_config()
+                },
+                ConfigError::ZeroInterval("wakeup_interval_seconds"),
+            ),
+            (
+                Config {
+                    nth_temp_humi: 0,
+                    enable_temp_humi: true,
+                    ..test_config()
+                },
+                ConfigError::ZeroInterval("nth_temp_humi"),
+            ),
+            (
+                Config {
+                    nth_water_temp: 0,
+                    ..test_config()
+                },
+                ConfigError::ZeroInterval("nth_water_temp"),
+            ),
+            (
+                Config {
+                    data_rate: 6,
+                    ..test_config()
+                },
+                ConfigError::OutOfRange("data_rate"),
+            ),
+            (
+                Config {
+                    batch_size: 7,
+                    ..test_config()
+                },
+                ConfigError::OutOfRange("batch_size"),
+            ),
+            (
+                Config {
+                    batch_size: 3,
+                    data_rate: 2,
+                    ..test_config()
+                },
+                ConfigError::OutOfRange("batch_size"),
+            ),
+            (
+                Config {
+                    tx_power_index: 0,
+                    ..test_config()
+                },
+                ConfigError::OutOfRange("tx_power_index"),
+            ),
+            (
+                Config {
+                    tx_power_index: 6,
+                    ..test_config()
+                },
+                ConfigError::OutOfRange("tx_power_index"),
+            ),
+            (
+                Config {
+                    calibration: Calibration {
+                        supply_gain: 0,
+                        ..Default::default()
+                    },
+                    ..test_config()
+                },
+                ConfigError::OutOfRange("supply_gain"),
+            ),
+            (
+                Config {
+                    nth_voltage: 0,
+                    ..test_config()
+                },
+                ConfigError::ZeroInterval("nth_voltage"),
+            ),
+            (
+                Config {
+                    activation: Activation::Abp {
+                        devaddr: [0; 4],
+                        nwkskey: [1; 16],
+                        appskey: [2; 16],
+                    },
+                    ..test_config()
+                },
+                ConfigError::ZeroDevAddr,
+            ),
+            (
+                Config {
+                    activation: Activation::Abp {
+                        devaddr: [1; 4],
+                        nwkskey: [0; 16],
+                        appskey: [2; 16],
+                    },
+                    ..test_config()
+                },
+                ConfigError::ZeroKey("nwkskey"),
+            ),
+            (
+                Config {
+                    activation: Activation::Abp {
+                        devaddr: [1; 4],
+                        nwkskey: [1; 16],
+                        appskey: [0; 16],
+                    },
+                    ..test_config()
+                },
+                ConfigError::ZeroKey("appskey"),
+            ),
+            (
+                Config {
+                    activation: Activation::Otaa {
+                        deveui: [0; 8],
+                        joineui: [0; 8],
+                        appkey: [3; 16],
+                    },
+                    ..test_config()
+                },
+                ConfigError::ZeroDevEui,
+            ),
+            (
+                Config {
+                    activation: Activation::Otaa {
+                        deveui: [4; 8],
+                        joineui: [0; 8],
+                        appkey: [0; 16],
+                    },
+                    ..test_config()
+                },
+                ConfigError::ZeroKey("appkey"),
+            ),
+        ];
+        for (config, error) in invalid.iter() {
+            assert_eq!(config.validate().as_ref(), Err(error));
+            // Invalid configs are rejected when reading them
+            assert_eq!(Config::from_slice(&config.serialize()).as_ref(), Err(error));
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_otaa() {
+        let config = Config {
+            activation: Activation::Otaa {
+                deveui: [0x00, 0x04, 0xA3, 0x0B, 0x00, 0x1A, 0x2B, 0x3C],
+                // An all-zero JoinEUI is valid
+                joineui: [0; 8],
+                appkey: [3; 16],
+            },
+            ..test_config()
+        };
+        let serialized = config.serialize();
+        assert_eq!(serialized[0x30], 0x01);
+        assert_eq!(Config::from_slice(&serialized).unwrap(), config);
+    }
+
+    #[test]
+    fn test_unsupported_activation_mode() {
+        let mut data = test_config().serialize();
+        data[0x30] = 0x02;
+        let checksum = crc32(&data[..CHECKSUM_OFFSET]);
+        data[CHECKSUM_OFFSET..].copy_from_slice(&checksum.to_le_bytes());
+        assert_eq!(
+            Config::from_slice(&data),
+            Err(ConfigError::UnsupportedActivationMode(0x02))
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_deserialize_toml() {
+        let abp: Config = toml::from_str(
+            r#"
+            version = 2
+            activation_mode = "abp"
+            devaddr = "26011b5e"
+            nwkskey = "01010101010101010101010101010101"
+            appskey = "02020202020202020202020202020202"
+            wakeup_interval_seconds = 123
+            nth_temp_humi = 1
+            nth_voltage = 2
+            nth_water_temp = 3
+            water_temp_resolution = 10
+            enable_temp_humi = false
+            batch_size = 4
+            data_rate = 3
+            tx_power_index = 3
+            adr = true
+            confirmed_every_n = 10
+
+            probe_order = [0x00000B4A1BFF, 0, 0x00000C1D2E3F]
+
+            [calibration]
+            water_temp_offsets = [-15, 0, 20]
+            sht_temp_offset = 30
+            sht_rh_offset = -200
+            supply_gain = 9800
+            "#,
+        )
+        .unwrap();
+        assert_eq!(abp, test_config());
+
+        let otaa: Config = toml::from_str(
+            r#"
+            version = 2
+            activation_mode = "otaa"
+            deveui = "0004a30b001a2b3c"
+            joineui = "0000000000000000"
+            appkey = "03030303030303030303030303030303"
+            wakeup_interval_seconds = 123
+            nth_temp_humi = 1
+            nth_voltage = 2
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            otaa.activation,
+            Activation::Otaa {
+                deveui: [0x00, 0x04, 0xA3, 0x0B, 0x00, 0x1A, 0x2B, 0x3C],
+                joineui: [0; 8],
+                appkey: [3; 16],
+            }
+        );
+        assert_eq!(otaa.batch_size, 0);
+        assert_eq!(otaa.nth_water_temp, 1);
+        assert_eq!(otaa.water_temp_resolution, Ds18b20Resolution::Bits12);
+        assert!(otaa.enable_water_temp && otaa.enable_temp_humi && otaa.enable_voltage);
+        assert_eq!(otaa.data_rate, DEFAULT_DATA_RATE);
+        assert_eq!(otaa.tx_power_index, DEFAULT_TX_POWER_INDEX);
+        assert!(!otaa.adr);
+        assert_eq!(otaa.confirmed_every_n, 0);
+        assert_eq!(otaa.calibration, Calibration::default());
+        assert_eq!(otaa.probe_order, [0; MAX_PROBES]);
+
+        let missing_key = toml::from_str::<Config>(
+            r#"
+            version = 2
+            activation_mode = "otaa"
+            deveui = "0004a30b001a2b3c"
+            appkey = "03030303030303030303030303030303"
+            wakeup_interval_seconds = 123
+            nth_temp_humi = 1
+            nth_voltage = 2
+            "#,
+        );
+        assert!(missing_key.is_err());
+
+        let too_many_probes = toml::from_str::<Config>(
+            r#"
+            version = 2
+            activation_mode = "otaa"
+            deveui = "0004a30b001a2b3c"
+            joineui = "0000000000000000"
+            appkey = "03030303030303030303030303030303"
+            wakeup_interval_seconds = 123
+            nth_temp_humi = 1
+            nth_voltage = 2
+            probe_order = [1, 2, 3, 4, 5]
+            "#,
+        );
+        assert!(too_many_probes.is_err());
+    }
+
+    /// Configs written before OTAA was supported (like the example in the
+    /// README of the first firmware release) have no `activation_mode`.
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_deserialize_toml_without_activation_mode() {
+        let config: Config = toml::from_str(
+            r#"
+            version = 1
+            devaddr = "26011b5e"
+            nwkskey = "11111111111111111111111111111111"
+            appskey = "22222222222222222222222222222222"
+            wakeup_interval_seconds = 900
+            nth_temp_humi = 1
+            nth_voltage = 4
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.version, ConfigVersion::V1);
+        assert_eq!(
+            config.activation,
+            Activation::Abp {
+                devaddr: [0x26, 0x01, 0x1B, 0x5E],
+                nwkskey: [0x11; 16],
+                appskey: [0x22; 16],
+            }
+        );
+        assert_eq!(config.wakeup_interval_seconds, 900);
+        assert_eq!(config.nth_temp_humi, 1);
+        assert_eq!(config.nth_voltage, 4);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_deserialize_toml_validates() {
+        let zero_devaddr = toml::from_str::<Config>(
+            r#"
+            version = 1
+            devaddr = "00000000"
+            nwkskey = "11111111111111111111111111111111"
+            appskey = "22222222222222222222222222222222"
+            wakeup_interval_seconds = 900
+            nth_temp_humi = 1
+            nth_voltage = 4
+            "#,
+        )
+        .unwrap_err();
+        assert!(
+            zero_devaddr
+                .to_string()
+                .contains(&ConfigError::ZeroDevAddr.to_string()),
+            "{}",
+            zero_devaddr
+        );
+
+        let zero_interval = toml::from_str::<Config>(
+            r#"
+            version = 2
+            devaddr = "26011b5e"
+            nwkskey = "11111111111111111111111111111111"
+            appskey = "22222222222222222222222222222222"
+            wakeup_interval_seconds = 0
+            nth_temp_humi = 1
+            nth_voltage = 4
+            "#,
+        )
+        .unwrap_err();
+        assert!(
+            zero_interval
+                .to_string()
+                .contains(&ConfigError::ZeroInterval("wakeup_interval_seconds").to_string()),
+            "{}",
+            zero_interval
+        );
+    }
+
+    #[test]
+    fn test_should_measure() {
+        let config = Config {
+            nth_water_temp: 1,
+            nth_temp_humi: 2,
+            nth_voltage: 3,
+            enable_temp_humi: true,
+            ..test_config()
+        };
+        let measured = |cycle| {
+            Sensor::ALL
+                .iter()
+                .map(|sensor| config.should_measure(*sensor, cycle))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(measured(0), [true, true, true]);
+        assert_eq!(measured(1), [true, false, false]);
+        assert_eq!(measured(2), [true, true, false]);
+        assert_eq!(measured(3), [true, false, true]);
+
+        // Disabled sensors are never measured
+        let config = Config {
+            enable_water_temp: false,
+            nth_water_temp: 0,
+            ..config
+        };
+        assert_eq!(config.validate(), Ok(()));
+        assert!(!config.should_measure(Sensor::WaterTemp, 0));
+        assert!(config.should_measure(Sensor::Voltage, 0));
+    }
+
+    #[test]
+    fn test_probe_positions() {
+        let config = Config {
+            probe_order: [0; MAX_PROBES],
+            ..test_config()
+        };
+        // Without a probe order, the probes are ordered by serial number
+        assert_eq!(
+            config.probe_positions(&[30, 10, 20]),
+            [Some(1), Some(2), Some(0), None]
+        );
+        assert_eq!(config.probe_positions(&[]), [None; MAX_PROBES]);
+        assert_eq!(
+            config.probe_positions(&[5, 4, 3, 2, 1]),
+            [Some(4), Some(3), Some(2), Some(1)]
+        );
+
+        // Listed probes keep their position, even if a probe before them is
+        // missing, unlisted probes fill the unused positions
+        let config = Config {
+            probe_order: [10, 20, 0, 30],
+            ..config
+        };
+        assert_eq!(
+            config.probe_positions(&[30, 40, 20, 5]),
+            [None, Some(2), Some(3), Some(0)]
+        );
+        assert_eq!(
+            config.probe_positions(&[10, 20, 30]),
+            [Some(0), Some(1), None, Some(2)]
+        );
+    }
+
+    #[test]
+    fn test_probe_order_serialization() {
+        let serialized = test_config().serialize();
+        assert_eq!(
+            serialized[0x60..0x6C],
+            [0xFF, 0x1B, 0x4A, 0x0B, 0x00, 0x00, 0, 0, 0, 0, 0, 0]
+        );
+
+        let config = Config {
+            probe_order: [MAX_PROBE_SERIAL + 1, 0, 0, 0],
+            ..test_config()
+        };
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::OutOfRange("probe_order"))
+        );
+    }
+
+    #[test]
+    fn test_water_temp_offsets_serialization() {
+        let serialized = test_config().serialize();
+        assert_eq!(serialized[0x58..0x5A], [0xF1, 0xFF]);
+        assert_eq!(
+            serialized[0x78..0x80],
+            [0x00, 0x00, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn test_sensor_enable_mask() {
+        let serialized = test_config().serialize();
+        assert_eq!(serialized[0x2D], 0b101);
+        assert_eq!(serialized[0x2E], 3);
+        assert_eq!(serialized[0x2F], 10);
+    }
+
+    #[test]
+    fn test_water_temp_resolution() {
+        let with_resolution_byte = |value: u8| {
+            let mut data = test_config().serialize();
+            data[0x2F] = value;
+            let checksum = crc32(&data[..CHECKSUM_OFFSET]);
+            data[CHECKSUM_OFFSET..].copy_from_slice(&checksum.to_le_bytes());
+            Config::from_slice(&data).map(|config| config.water_temp_resolution)
+        };
+        assert_eq!(with_resolution_byte(9), Ok(Ds18b20Resolution::Bits9));
+        assert_eq!(with_resolution_byte(12), Ok(Ds18b20Resolution::Bits12));
+        // Configurations written without the resolution contain 0
+        assert_eq!(with_resolution_byte(0), Ok(Ds18b20Resolution::Bits12));
+        assert_eq!(
+            with_resolution_byte(13),
+            Err(ConfigError::OutOfRange("water_temp_resolution"))
+        );
+    }
+
+    #[test]
+    fn test_ds18b20_resolution() {
+        let resolutions = [
+            (Ds18b20Resolution::Bits9, 0x1F, 94),
+            (Ds18b20Resolution::Bits10, 0x3F, 188),
+            (Ds18b20Resolution::Bits11, 0x5F, 375),
+            (Ds18b20Resolution::Bits12, 0x7F, 750),
+        ];
+        for (resolution, register, time) in resolutions.iter() {
+            assert_eq!(resolution.config_register(), *register);
+            assert_eq!(
+                Ds18b20Resolution::from_config_register(*register),
+                *resolution
+            );
+            assert_eq!(resolution.conversion_time_ms(), *time);
+        }
+
+        // 0x0191 = 25.0625 °C, -0x0191 = -25.0625 °C
+        assert_eq!(Ds18b20Resolution::Bits12.mask_raw(0x0191), 0x0191);
+        assert_eq!(Ds18b20Resolution::Bits11.mask_raw(0x0191), 0x0190);
+        assert_eq!(Ds18b20Resolution::Bits10.mask_raw(0x0197), 0x0194);
+        assert_eq!(Ds18b20Resolution::Bits9.mask_raw(0x0197), 0x0190);
+        assert_eq!(Ds18b20Resolution::Bits9.mask_raw(-0x0191), -0x0198);
+    }
 }
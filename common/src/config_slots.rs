@@ -0,0 +1,308 @@
+//! A/B configuration slots with a generation counter.
+//!
+//! The configuration is stored in two slots. An update is always written to
+//! the slot that does not hold the current configuration, and the generation
+//! counter is written last. If the device loses power while writing, the
+//! checksum of the partially written slot does not match, and the other slot
+//! is used.
+//!
+//! ## Memory Map
+//!
+//! Offsets are relative to [`config::BASE_ADDR`](crate::config::BASE_ADDR).
+//!
+//! ```text
+//!             0           8          16          24          32
+//!             +-----------+-----------+-----------+-----------+
+//! 0x0000      | Slot A: Configuration (see `config` module)   |
+//! ...         |                                               |
+//!             +-----------+-----------+-----------+-----------+
+//! 0x0084      | Slot A: Generation (u32, LE)                  |
+//!             +-----------+-----------+-----------+-----------+
+//! 0x0100      | Slot B: Configuration                         |
+//! ...         |                                               |
+//!             +-----------+-----------+-----------+-----------+
+//! 0x0184      | Slot B: Generation (u32, LE)                  |
+//!             +-----------+-----------+-----------+-----------+
+//! ```
+//!
+//! Each slot has 256 bytes of room, so that the configuration block can grow
+//! in later versions without moving slot B.
+//!
+//! The valid slot with the newest generation is used. Generations are compared
+//! with wrap-around, so the counter may overflow. Slot A is at the same
+//! address as the single configuration block of older firmware versions, so
+//! these configurations are still found.
+
+use core::convert::TryInto;
+
+use crate::{
+    config::{Config, ConfigError, CONFIG_DATA_SIZE},
+    memory_map,
+};
+
+/// Offsets of the two slots.
+pub const SLOT_OFFSETS: [usize; 2] = [0x000, 0x100];
+
+/// Offset of the generation counter within a slot.
+const GENERATION_OFFSET: usize = CONFIG_DATA_SIZE;
+
+/// Size of a slot (configuration and generation counter).
+const SLOT_SIZE: usize = GENERATION_OFFSET + 4;
+
+/// Size of the EEPROM region holding both slots.
+pub const CONFIG_SLOTS_SIZE: usize = SLOT_OFFSETS[1] + SLOT_SIZE;
+
+const _: () = assert!(
+    SLOT_SIZE <= SLOT_OFFSETS[1] - SLOT_OFFSETS[0]
+        && CONFIG_SLOTS_SIZE <= memory_map::CONFIG_SLOTS.len,
+    "Config slots do not fit into their EEPROM region"
+);
+
+/// Word-addressable EEPROM holding the configuration slots.
+///
+/// Offsets are relative to the start of the slot region.
+pub trait Eeprom {
+    type Error;
+
+    /// Read `buf.len()` bytes starting at `offset`.
+    fn read(&self, offset: usize, buf: &mut [u8]);
+
+    /// Write a word at `offset`, which must be word aligned.
+    fn write_word(&mut self, offset: usize, word: u32) -> Result<(), Self::Error>;
+}
+
+/// Location of a loaded or stored configuration.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SlotInfo {
+    /// Index of the slot (0 = A, 1 = B)
+    pub slot: usize,
+    /// Generation counter of the slot
+    pub generation: u32,
+}
+
+/// Return whether generation `a` is newer than generation `b`.
+fn is_newer(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+/// Read and validate the slot with the given index.
+fn read_slot<E: Eeprom>(eeprom: &E, slot: usize) -> Result<(Config, SlotInfo), ConfigError> {
+    let mut data = [0; SLOT_SIZE];
+    eeprom.read(SLOT_OFFSETS[slot], &mut data);
+    let config = Config::from_slice(&data[..CONFIG_DATA_SIZE])?;
+    let generation = u32::from_le_bytes(data[GENERATION_OFFSET..].try_into().unwrap());
+    Ok((config, SlotInfo { slot, generation }))
+}
+
+/// Load the configuration from the valid slot with the newest generation.
+///
+/// If no slot is valid, the error of slot A is returned.
+pub fn load<E: Eeprom>(eeprom: &E) -> Result<(Config, SlotInfo), ConfigError> {
+    match (read_slot(eeprom, 0), read_slot(eeprom, 1)) {
+        (Ok(a), Ok(b)) if is_newer(b.1.generation, a.1.generation) => Ok(b),
+        (Ok(a), _) => Ok(a),
+        (Err(_), Ok(b)) => Ok(b),
+        (Err(e), Err(_)) => Err(e),
+    }
+}
+
+/// Store the configuration in the slot that does not hold the current
+/// configuration, with the next generation.
+///
+/// The configuration is written first and the generation counter last, so
+/// that an interrupted write never replaces the current configuration.
+pub fn store<E: Eeprom>(eeprom: &mut E, config: &Config) -> Result<SlotInfo, E::Error> {
+    let info = match load(eeprom) {
+        Ok((_, current)) => SlotInfo {
+            slot: 1 - current.slot,
+            generation: current.generation.wrapping_add(1),
+        },
+        Err(_) => SlotInfo {
+            slot: 0,
+            generation: 0,
+        },
+    };
+    let offset = SLOT_OFFSETS[info.slot];
+    for (i, word) in config.serialize().chunks(4).enumerate() {
+        eeprom.write_word(offset + i * 4, u32::from_le_bytes(word.try_into().unwrap()))?;
+    }
+    eeprom.write_word(offset + GENERATION_OFFSET, info.generation)?;
+    Ok(info)
+}
+
+/// Return the EEPROM image of a freshly provisioned configuration.
+///
+/// The configuration is stored in slot A with generation 0, slot B is
+/// cleared.
+pub fn initial_image(config: &Config) -> [u8; CONFIG_SLOTS_SIZE] {
+    let mut image = [0; CONFIG_SLOTS_SIZE];
+    image[..CONFIG_DATA_SIZE].copy_from_slice(&config.serialize());
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::test_config;
+
+    /// RAM-backed EEPROM fake.
+    struct RamEeprom {
+        data: [u8; CONFIG_SLOTS_SIZE],
+        /// Number of remaining word writes before a simulated power loss
+        writes_left: Option<usize>,
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct PowerLoss;
+
+    impl RamEeprom {
+        fn new() -> Self {
+            Self {
+                data: [0; CONFIG_SLOTS_SIZE],
+                writes_left: None,
+            }
+        }
+    }
+
+    impl Eeprom for RamEeprom {
+        type Error = PowerLoss;
+
+        fn read(&self, offset: usize, buf: &mut [u8]) {
+            buf.copy_from_slice(&self.data[offset..offset + buf.len()]);
+        }
+
+        fn write_word(&mut self, offset: usize, word: u32) -> Result<(), PowerLoss> {
+            assert_eq!(offset % 4, 0, "Unaligned write");
+            if let Some(writes_left) = self.writes_left.as_mut() {
+                if *writes_left == 0 {
+                    return Err(PowerLoss);
+                }
+                *writes_left -= 1;
+            }
+            self.data[offset..offset + 4].copy_from_slice(&word.to_le_bytes());
+            Ok(())
+        }
+    }
+
+    fn config(wakeup_interval_seconds: u16) -> Config {
+        Config {
+            wakeup_interval_seconds,
+            ..test_config()
+        }
+    }
+
+    #[test]
+    fn test_slot_layout() {
+        // The slots must not overlap
+        assert!(SLOT_SIZE <= SLOT_OFFSETS[1] - SLOT_OFFSETS[0]);
+        assert_eq!(GENERATION_OFFSET % 4, 0);
+    }
+
+    #[test]
+    fn test_load_empty() {
+        let eeprom = RamEeprom::new();
+        assert_eq!(load(&eeprom), Err(ConfigError::UnsupportedVersion(0)));
+    }
+
+    #[test]
+    fn test_initial_image() {
+        let mut eeprom = RamEeprom::new();
+        eeprom.data = initial_image(&config(900));
+        assert_eq!(
+            load(&eeprom),
+            Ok((
+                config(900),
+                SlotInfo {
+                    slot: 0,
+                    generation: 0
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_store_alternates_slots() {
+        let mut eeprom = RamEeprom::new();
+        eeprom.data = initial_image(&config(900));
+
+        for (i, interval) in [600, 300, 120].iter().enumerate() {
+            let info = store(&mut eeprom, &config(*interval)).unwrap();
+            let expected = SlotInfo {
+                slot: (i + 1) % 2,
+                generation: i as u32 + 1,
+            };
+            assert_eq!(info, expected);
+            assert_eq!(load(&eeprom), Ok((config(*interval), expected)));
+        }
+    }
+
+    #[test]
+    fn test_store_into_empty_eeprom() {
+        let mut eeprom = RamEeprom::new();
+        let info = store(&mut eeprom, &config(900)).unwrap();
+        assert_eq!(info.slot, 0);
+        assert_eq!(load(&eeprom).unwrap().0, config(900));
+    }
+
+    #[test]
+    fn test_power_loss() {
+        let words = CONFIG_DATA_SIZE / 4 + 1;
+        for writes in 0..words {
+            let mut eeprom = RamEeprom::new();
+            eeprom.data = initial_image(&config(900));
+            store(&mut eeprom, &config(600)).unwrap();
+
+            // Interrupt the next update after the given number of words
+            eeprom.writes_left = Some(writes);
+            assert_eq!(store(&mut eeprom, &config(300)), Err(PowerLoss));
+            let (loaded, info) = load(&eeprom).unwrap();
+            assert_eq!(loaded, config(600), "Power loss after {} words", writes);
+            assert_eq!(info.generation, 1);
+        }
+    }
+
+    #[test]
+    fn test_corrupted_slot() {
+        let mut eeprom = RamEeprom::new();
+        eeprom.data = initial_image(&config(900));
+        store(&mut eeprom, &config(600)).unwrap();
+
+        // Corrupt the newer slot B, slot A is used
+        eeprom.data[SLOT_OFFSETS[1] + 0x28] ^= 0xFF;
+        let (loaded, info) = load(&eeprom).unwrap();
+        assert_eq!(loaded, config(900));
+        assert_eq!(info.slot, 0);
+
+        // The next update replaces the corrupted slot
+        let info = store(&mut eeprom, &config(300)).unwrap();
+        assert_eq!(
+            info,
+            SlotInfo {
+                slot: 1,
+                generation: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_generation_wraparound() {
+        assert!(is_newer(1, 0));
+        assert!(!is_newer(0, 1));
+        assert!(!is_newer(5, 5));
+        assert!(is_newer(0, u32::MAX));
+
+        let mut eeprom = RamEeprom::new();
+        eeprom.data = initial_image(&config(900));
+        eeprom.data[GENERATION_OFFSET..SLOT_SIZE].copy_from_slice(&u32::MAX.to_le_bytes());
+        let info = store(&mut eeprom, &config(600)).unwrap();
+        assert_eq!(
+            info,
+            SlotInfo {
+                slot: 1,
+                generation: 0
+            }
+        );
+        assert_eq!(load(&eeprom).unwrap().0, config(600));
+    }
+}
@@ -1,5 +1,11 @@
 #![cfg_attr(not(test), no_std)]
 //! This crate holds all code which is used in the gfroerli firmware and command line utilities.
 
+pub mod calibration;
+pub mod command;
 pub mod config;
+pub mod config_slots;
 pub mod measurement;
+pub mod memory_map;
+pub mod panic;
+pub mod status;
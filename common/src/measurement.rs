@@ -1,6 +1,10 @@
+use core::{convert::TryInto, fmt};
+
 use bitfield::{bitfield, Bit, BitRange};
 
-#[derive(Copy, Clone, Default)]
+use crate::config::MAX_PROBES;
+
+#[derive(Copy, Clone, Default, Debug, PartialEq)]
 pub struct U12(u16);
 
 impl U12 {
@@ -14,90 +18,1119 @@ impl U12 {
     }
 }
 
-pub const MAX_MSG_LEN: usize = 8;
+/// A signed 12 bit value (two's complement).
+#[derive(Copy, Clone, Default, Debug, PartialEq)]
+pub struct I12(i16);
+
+impl I12 {
+    pub const MIN: i16 = -0x800;
+    pub const MAX: i16 = 0x7FF;
+
+    pub fn new(value: i16) -> Self {
+        Self(value.clamp(Self::MIN, Self::MAX))
+    }
+
+    /// Return the inner i16 (sign extended).
+    pub fn as_i16(&self) -> i16 {
+        self.0
+    }
+}
+
+/// Device status flags, sent along with a measurement.
+///
+/// The flags report problems which would otherwise only be visible as missing
+/// values or in the serial log. The low byte is sent as `Status` field, the
+/// high byte as `Status_2` field (only if any of its flags is set).
+#[derive(Copy, Clone, Default, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(transparent))]
+pub struct StatusFlags(u16);
+
+impl StatusFlags {
+    /// No DS18B20 water temperature sensor was found on the one-wire bus
+    pub const DS18B20_NOT_FOUND: Self = Self(1 << 0);
+    /// Communication with the DS18B20 failed (bus error)
+    pub const DS18B20_ERROR: Self = Self(1 << 1);
+    /// The SHTC3 measurement result had an invalid CRC
+    pub const SHTC3_CRC_ERROR: Self = Self(1 << 2);
+    /// Communication with the SHTC3 failed (I²C bus error)
+    pub const SHTC3_I2C_ERROR: Self = Self(1 << 3);
+    /// The previous boot ended with a panic
+    pub const PANICKED: Self = Self(1 << 4);
+    // Bit 5 is reserved. Without a valid configuration the device has no
+    // LoRaWAN credentials, so a "config defaults" flag could never be sent.
+    /// The supply voltage is low
+    pub const LOW_BATTERY: Self = Self(1 << 6);
+    /// The DS18B20 returned its power-on reset value (85 °C), it probably
+    /// browned out during the conversion
+    pub const DS18B20_POWER_ON_RESET: Self = Self(1 << 7);
+    /// The DS18B20 scratchpad had an invalid CRC
+    pub const DS18B20_CRC_ERROR: Self = Self(1 << 8);
+    /// The DS18B20 returned a value outside of its measurement range
+    pub const DS18B20_OUT_OF_RANGE: Self = Self(1 << 9);
+
+    pub fn from_bits(bits: u16) -> Self {
+        Self(bits)
+    }
+
+    pub fn bits(&self) -> u16 {
+        self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Return whether all flags in `other` are set.
+    pub fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Set all flags in `other`.
+    pub fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+}
+
+/// FPort of the legacy format (four little endian floats).
+pub const FPORT_LEGACY: u8 = 1;
+
+/// FPort of the measurement format (data mask followed by data).
+pub const FPORT_MEASUREMENT: u8 = 2;
+
+/// FPort of the batch format (multiple measurements with time offsets).
+pub const FPORT_BATCH: u8 = 3;
+
+/// Maximum length of an encoded measurement message containing all known values.
+pub const MAX_MSG_LEN: usize = max_msg_len(&SCHEMA);
 
-#[derive(Copy, Clone, Default)]
+/// Maximum number of data mask bytes.
+pub const MAX_DATA_MASK_LEN: usize = 4;
+
+/// Number of value bits per data mask byte. The most significant bit of every
+/// data mask byte indicates whether another data mask byte follows.
+pub const DATA_MASK_BITS_PER_BYTE: usize = 7;
+
+/// Length of a message in the legacy format.
+pub const LEGACY_MSG_LEN: usize = 16;
+
+/// Maximum number of measurements in a batch message.
+pub const MAX_BATCH_SIZE: usize = 8;
+
+/// Length of the header of every record in a batch message (the age).
+const BATCH_RECORD_HEADER_LEN: usize = 2;
+
+/// Maximum length of an encoded record in a batch message.
+pub const MAX_BATCH_RECORD_LEN: usize = BATCH_RECORD_HEADER_LEN + MAX_MSG_LEN;
+
+/// Maximum length of an encoded batch message.
+pub const MAX_BATCH_MSG_LEN: usize = 1 + MAX_BATCH_SIZE * MAX_BATCH_RECORD_LEN;
+
+/// Return the maximum application payload length of an uplink at the given
+/// EU868 data rate (LoRaWAN Regional Parameters, without FOpts).
+pub const fn max_payload_len(data_rate: u8) -> usize {
+    match data_rate {
+        0..=2 => 51,
+        3 => 115,
+        _ => 222,
+    }
+}
+
+/// Return the number of records that fit into a batch message of at most
+/// `max_len` bytes, even if every record contains all known values.
+pub const fn max_batch_size(max_len: usize) -> usize {
+    let records = max_len.saturating_sub(1) / MAX_BATCH_RECORD_LEN;
+    if records < MAX_BATCH_SIZE {
+        records
+    } else {
+        MAX_BATCH_SIZE
+    }
+}
+
+/// FPort of the series format (delta compressed water temperatures).
+pub const FPORT_SERIES: u8 = 4;
+
+/// Maximum number of values in a series message.
+pub const MAX_SERIES_LEN: usize = 16;
+
+/// Number of bits of the series header: Count (8 bits), delta width (4 bits)
+/// and the first value (12 bits).
+const SERIES_HEADER_BITS: usize = 8 + 4 + 12;
+
+/// Maximum width of a delta in a series message. The difference of two 12 bit
+/// values needs at most 13 bits.
+const MAX_DELTA_WIDTH: usize = 13;
+
+/// Maximum length of an encoded series message.
+pub const MAX_SERIES_MSG_LEN: usize =
+    bits_to_bytes(SERIES_HEADER_BITS + (MAX_SERIES_LEN - 1) * MAX_DELTA_WIDTH);
+
+#[derive(Copy, Clone, Default, Debug, PartialEq)]
 pub struct MeasurementMessage {
     pub t_water: Option<U12>,
     pub t_inside: Option<u16>,
     pub rh_inside: Option<u16>,
     pub v_supply: Option<U12>,
+    pub t_water_signed: Option<I12>,
+    pub status: Option<StatusFlags>,
+    /// Water temperature of the second probe (in probe order)
+    pub t_water_2: Option<I12>,
+    /// Water temperature of the third probe (in probe order)
+    pub t_water_3: Option<I12>,
+    /// Water temperature of the fourth probe (in probe order)
+    pub t_water_4: Option<I12>,
+}
+
+/// Errors that can occur when decoding an uplink message.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DecodeError {
+    /// The message is empty, not even the data mask is present.
+    Empty,
+    /// The data mask has reserved bits set, or consists of more than
+    /// `MAX_DATA_MASK_LEN` bytes.
+    UnsupportedDataMask(u32),
+    /// The message is shorter than announced by the data mask.
+    TooShort,
+    /// The message is longer than announced by the data mask.
+    TooLong,
+    /// The FPort does not correspond to a known message format.
+    UnsupportedFPort(u8),
+    /// The batch message contains more than `MAX_BATCH_SIZE` measurements.
+    UnsupportedBatchSize(u8),
+    /// The series message contains more than `MAX_SERIES_LEN` values.
+    UnsupportedSeriesLength(u8),
+    /// The series message uses deltas wider than 13 bits.
+    UnsupportedDeltaWidth(u8),
+    /// The message format version is not supported.
+    UnsupportedVersion(u8),
+    /// The downlink command opcode is not supported.
+    UnsupportedCommand(u8),
+    /// The command result in a command acknowledgement is not supported.
+    UnsupportedCommandResult(u8),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "Empty message"),
+            Self::UnsupportedDataMask(mask) => {
+                write!(f, "Unsupported data mask (0b{:b})", mask)
+            }
+            Self::TooShort => write!(f, "Message too short"),
+            Self::TooLong => write!(f, "Message too long"),
+            Self::UnsupportedFPort(fport) => write!(f, "Unsupported FPort ({})", fport),
+            Self::UnsupportedBatchSize(count) => write!(f, "Unsupported batch size ({})", count),
+            Self::UnsupportedSeriesLength(count) => {
+                write!(f, "Unsupported series length ({})", count)
+            }
+            Self::UnsupportedDeltaWidth(width) => {
+                write!(f, "Unsupported delta width ({})", width)
+            }
+            Self::UnsupportedVersion(version) => {
+                write!(f, "Unsupported message version ({})", version)
+            }
+            Self::UnsupportedCommand(opcode) => {
+                write!(f, "Unsupported command (0x{:02x})", opcode)
+            }
+            Self::UnsupportedCommandResult(result) => {
+                write!(f, "Unsupported command result ({})", result)
+            }
+        }
+    }
+}
+
+/// Byte buffer that can hold an `EncodedMeasurement`.
+pub trait Buffer: AsRef<[u8]> + AsMut<[u8]> {}
+
+impl<T: AsRef<[u8]> + AsMut<[u8]>> Buffer for T {}
+
+/// A field of the measurement message.
+///
+/// The discriminant is the bit of the field in the data mask.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Field {
+    TWater = 0,
+    TInside = 1,
+    RhInside = 2,
+    VSupply = 3,
+    TWaterSigned = 4,
+    Status = 5,
+    TWater2 = 6,
+    TWater3 = 7,
+    TWater4 = 8,
+    Status2 = 9,
+}
+
+impl Field {
+    /// Fields of the water temperatures, in probe order.
+    pub const WATER_TEMPERATURES: [Field; MAX_PROBES] = [
+        Field::TWaterSigned,
+        Field::TWater2,
+        Field::TWater3,
+        Field::TWater4,
+    ];
+
+    /// Return the bit of this field in the data mask.
+    pub const fn mask_bit(self) -> usize {
+        self as usize
+    }
+
+    /// Return the position of the flags of this field in [`StatusFlags`]
+    /// (only relevant for the status fields).
+    pub const fn status_shift(self) -> usize {
+        match self {
+            Field::Status2 => 8,
+            _ => 0,
+        }
+    }
+}
+
+/// Conversion from a raw value to a physical value.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Conversion {
+    /// DS18B20 temperature in 1/16 °C
+    Ds18b20,
+    /// SHTC3 temperature
+    Shtc3Temperature,
+    /// SHTC3 relative humidity
+    Shtc3Humidity,
+    /// Supply voltage, offset by 2000 mV
+    Supply,
+    /// No physical value, the raw value is a set of flags
+    Flags,
+}
+
+impl Conversion {
+    /// Convert a raw value to a physical value.
+    pub fn apply(self, raw: i32) -> f32 {
+        match self {
+            Self::Ds18b20 => raw as f32 / 16.0,
+            Self::Shtc3Temperature => -45.0 + 175.0 * (raw as f32 / 65536.0),
+            Self::Shtc3Humidity => 100.0 * (raw as f32 / 65536.0),
+            Self::Supply => (raw + 2000) as f32,
+            Self::Flags => raw as f32,
+        }
+    }
+
+    /// Return the conversion formula, as shown in the documentation.
+    pub fn formula(self) -> &'static str {
+        match self {
+            Self::Ds18b20 => "v / 16",
+            Self::Shtc3Temperature => "-45 + 175 * (v / 2^16)",
+            Self::Shtc3Humidity => "100 * (v / 2^16)",
+            Self::Supply => "v + 2000",
+            Self::Flags => "see below",
+        }
+    }
 }
 
-trait MeasurementValue {
-    const SIZE: usize;
-    fn encode(&self, output: &mut EncodedMeasurement<[u8; MAX_MSG_LEN]>, bit_index: &mut usize);
+/// Description of a field in the measurement message.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FieldSchema {
+    pub field: Field,
+    /// Name as shown in the documentation
+    pub name: &'static str,
+    /// Width in bits
+    pub width: usize,
+    /// Whether the value is a two's complement signed value
+    pub signed: bool,
+    pub conversion: Conversion,
+    /// Unit of the converted value
+    pub unit: &'static str,
+}
+
+/// Schema of the measurement message (FPort 2).
+///
+/// The fields are listed in the order of their data mask bits, which is also
+/// the order in which the values are encoded. This table drives the encoder,
+/// the decoder and the table in `docs/message-format.md`.
+pub const SCHEMA: [FieldSchema; 10] = [
+    FieldSchema {
+        field: Field::TWater,
+        name: "T_water",
+        width: 12,
+        signed: false,
+        conversion: Conversion::Ds18b20,
+        unit: "°C",
+    },
+    FieldSchema {
+        field: Field::TInside,
+        name: "T_inside",
+        width: 16,
+        signed: false,
+        conversion: Conversion::Shtc3Temperature,
+        unit: "°C",
+    },
+    FieldSchema {
+        field: Field::RhInside,
+        name: "RH_inside",
+        width: 16,
+        signed: false,
+        conversion: Conversion::Shtc3Humidity,
+        unit: "%RH",
+    },
+    FieldSchema {
+        field: Field::VSupply,
+        name: "V_supply",
+        width: 12,
+        signed: false,
+        conversion: Conversion::Supply,
+        unit: "mV",
+    },
+    FieldSchema {
+        field: Field::TWaterSigned,
+        name: "T_water (signed)",
+        width: 12,
+        signed: true,
+        conversion: Conversion::Ds18b20,
+        unit: "°C",
+    },
+    FieldSchema {
+        field: Field::Status,
+        name: "Status",
+        width: 8,
+        signed: false,
+        conversion: Conversion::Flags,
+        unit: "",
+    },
+    FieldSchema {
+        field: Field::TWater2,
+        name: "T_water_2",
+        width: 12,
+        signed: true,
+        conversion: Conversion::Ds18b20,
+        unit: "°C",
+    },
+    FieldSchema {
+        field: Field::TWater3,
+        name: "T_water_3",
+        width: 12,
+        signed: true,
+        conversion: Conversion::Ds18b20,
+        unit: "°C",
+    },
+    FieldSchema {
+        field: Field::TWater4,
+        name: "T_water_4",
+        width: 12,
+        signed: true,
+        conversion: Conversion::Ds18b20,
+        unit: "°C",
+    },
+    FieldSchema {
+        field: Field::Status2,
+        name: "Status_2",
+        width: 8,
+        signed: false,
+        conversion: Conversion::Flags,
+        unit: "",
+    },
+];
+
+/// Return the data mask bits used by the fields in the schema.
+const fn known_data_mask_bits(schema: &[FieldSchema]) -> u32 {
+    let mut mask = 0;
+    let mut i = 0;
+    while i < schema.len() {
+        mask |= 1 << schema[i].field.mask_bit();
+        i += 1;
+    }
+    mask
 }
 
-impl MeasurementValue for U12 {
-    const SIZE: usize = 12;
-    fn encode(&self, output: &mut EncodedMeasurement<[u8; MAX_MSG_LEN]>, bit_index: &mut usize) {
-        output.set_bit_range(*bit_index + Self::SIZE - 1, *bit_index, self.0);
-        *bit_index += Self::SIZE;
+/// Return the length of a message containing all fields in the schema.
+const fn max_msg_len(schema: &[FieldSchema]) -> usize {
+    let mut bits = 0;
+    let mut max_mask_bit = 0;
+    let mut i = 0;
+    while i < schema.len() {
+        bits += schema[i].width;
+        if schema[i].field.mask_bit() > max_mask_bit {
+            max_mask_bit = schema[i].field.mask_bit();
+        }
+        i += 1;
     }
+    let mask_len = max_mask_bit / DATA_MASK_BITS_PER_BYTE + 1;
+    mask_len + bits_to_bytes(bits)
 }
 
-impl MeasurementValue for u16 {
-    const SIZE: usize = 16;
-    fn encode(&self, output: &mut EncodedMeasurement<[u8; MAX_MSG_LEN]>, bit_index: &mut usize) {
-        output.set_bit_range(*bit_index + Self::SIZE - 1, *bit_index, *self);
-        *bit_index += Self::SIZE;
+/// Write the schema as markdown table.
+pub fn write_schema_table(f: &mut impl fmt::Write) -> fmt::Result {
+    writeln!(
+        f,
+        "|bit|value           |type|conversion              |unit|"
+    )?;
+    writeln!(
+        f,
+        "|---|----------------|----|------------------------|----|"
+    )?;
+    for field in SCHEMA.iter() {
+        writeln!(
+            f,
+            "|{:<3}|{:<16}|{}{:<3}|{:<24}|{:<4}|",
+            field.field.mask_bit(),
+            field.name,
+            if field.signed { 'i' } else { 'u' },
+            field.width,
+            field.conversion.formula(),
+            field.unit,
+        )?;
     }
+    Ok(())
 }
 
 bitfield! {
     pub struct EncodedMeasurement(MSB0 [u8]);
 }
 
-/// The encoder encodes `MeasurementValue`s into an `EncodedMeasurement` output buffer.
+/// The encoder encodes raw values into an `EncodedMeasurement` output buffer.
 ///
-/// It keeps track of the offset and calculates the number of bytes written when finishing.
-struct Encoder<'a> {
+/// The data mask is written when creating the encoder. It keeps track of the
+/// offset and calculates the number of bytes written when finishing.
+struct Encoder<'a, B: Buffer> {
     bit_index: usize,
-    data_mask: u8,
-    output: &'a mut EncodedMeasurement<[u8; MAX_MSG_LEN]>,
+    data_mask: u32,
+    output: &'a mut EncodedMeasurement<B>,
 }
 
-impl<'a> Encoder<'a> {
-    fn new(output: &'a mut EncodedMeasurement<[u8; MAX_MSG_LEN]>) -> Self {
+impl<'a, B: Buffer> Encoder<'a, B> {
+    fn new(output: &'a mut EncodedMeasurement<B>, data_mask: u32) -> Self {
+        let mut bit_index = 0;
+        let mut remaining = data_mask;
+        loop {
+            let mut mask_byte = remaining as u8 & 0x7F;
+            remaining >>= DATA_MASK_BITS_PER_BYTE;
+            mask_byte.set_bit(DATA_MASK_BITS_PER_BYTE, remaining != 0);
+            output.0.as_mut()[bit_index / 8] = mask_byte;
+            bit_index += 8;
+            if remaining == 0 {
+                break;
+            }
+        }
         Self {
-            bit_index: 8,
-            data_mask: 0,
+            bit_index,
+            data_mask,
             output,
         }
     }
 
-    fn encode(&mut self, mask_bit: usize, value: &impl MeasurementValue) {
-        value.encode(self.output, &mut self.bit_index);
-        self.data_mask.set_bit(mask_bit, true);
+    fn encode(&mut self, field: &FieldSchema, raw: i32) {
+        debug_assert!(
+            self.data_mask.bit(field.field.mask_bit()),
+            "Value not in data mask"
+        );
+        // Note: Only the lowest `width` bits are written, so negative values
+        // are stored as two's complement.
+        self.output
+            .set_bit_range(self.bit_index + field.width - 1, self.bit_index, raw as u32);
+        self.bit_index += field.width;
     }
 
     /// Finish encoding, return the number of bytes encoded.
     fn finish(self) -> usize {
-        self.output.0[0] = self.data_mask;
-        (self.bit_index + 4) / 8
+        bits_to_bytes(self.bit_index)
+    }
+}
+
+/// The decoder decodes raw values from an `EncodedMeasurement` input buffer.
+///
+/// It is the counterpart of the `Encoder`: It reads the data mask, keeps track
+/// of the offset and verifies that the input length matches the data mask.
+struct Decoder {
+    bit_index: usize,
+    data_mask: u32,
+    length: usize,
+    input: EncodedMeasurement<[u8; MAX_MSG_LEN]>,
+}
+
+impl Decoder {
+    /// Create a decoder for the given input, which must not be longer than
+    /// `MAX_MSG_LEN`.
+    fn new(input: &[u8]) -> Result<Self, DecodeError> {
+        if input.is_empty() {
+            return Err(DecodeError::Empty);
+        }
+
+        // Read data mask bytes until the continuation bit is not set
+        let mut data_mask = 0;
+        let mut mask_len = 0;
+        loop {
+            let mask_byte = *input.get(mask_len).ok_or(DecodeError::TooShort)?;
+            data_mask |= ((mask_byte & 0x7F) as u32) << (mask_len * DATA_MASK_BITS_PER_BYTE);
+            mask_len += 1;
+            if !mask_byte.bit(DATA_MASK_BITS_PER_BYTE) {
+                break;
+            }
+            if mask_len == MAX_DATA_MASK_LEN {
+                return Err(DecodeError::UnsupportedDataMask(data_mask));
+            }
+        }
+
+        let mut buf = [0u8; MAX_MSG_LEN];
+        buf[..input.len()].copy_from_slice(input);
+        Ok(Self {
+            bit_index: mask_len * 8,
+            data_mask,
+            length: input.len(),
+            input: EncodedMeasurement(buf),
+        })
+    }
+
+    fn decode(&mut self, field: &FieldSchema) -> Result<Option<i32>, DecodeError> {
+        if !self.data_mask.bit(field.field.mask_bit()) {
+            return Ok(None);
+        }
+        if self.bit_index + field.width > self.length * 8 {
+            return Err(DecodeError::TooShort);
+        }
+        let (msb, lsb) = (self.bit_index + field.width - 1, self.bit_index);
+        let raw = if field.signed {
+            // Reading into a signed type sign-extends the value
+            self.input.bit_range(msb, lsb)
+        } else {
+            let raw: u32 = self.input.bit_range(msb, lsb);
+            raw as i32
+        };
+        self.bit_index += field.width;
+        Ok(Some(raw))
+    }
+
+    /// Finish decoding, return the number of bytes decoded.
+    fn finish(self) -> usize {
+        bits_to_bytes(self.bit_index)
     }
 }
 
 impl MeasurementMessage {
+    /// Bits in the data mask which are used by known values.
+    const KNOWN_DATA_MASK_BITS: u32 = known_data_mask_bits(&SCHEMA);
+
+    /// Return the raw value of a field, if available.
+    fn raw_value(&self, field: Field) -> Option<i32> {
+        match field {
+            Field::TWater => self.t_water.map(|v| v.as_u16() as i32),
+            Field::TInside => self.t_inside.map(i32::from),
+            Field::RhInside => self.rh_inside.map(i32::from),
+            Field::VSupply => self.v_supply.map(|v| v.as_u16() as i32),
+            Field::TWaterSigned => self.t_water_signed.map(|v| v.as_i16() as i32),
+            Field::Status => self.status.map(|v| (v.bits() & 0xFF) as i32),
+            Field::TWater2 => self.t_water_2.map(|v| v.as_i16() as i32),
+            Field::TWater3 => self.t_water_3.map(|v| v.as_i16() as i32),
+            Field::TWater4 => self.t_water_4.map(|v| v.as_i16() as i32),
+            Field::Status2 => self
+                .status
+                .map(|v| (v.bits() >> 8) as i32)
+                .filter(|&bits| bits != 0),
+        }
+    }
+
+    /// Set the raw value of a field.
+    fn set_raw_value(&mut self, field: Field, raw: i32) {
+        match field {
+            Field::TWater => self.t_water = Some(U12::new(raw as u16)),
+            Field::TInside => self.t_inside = Some(raw as u16),
+            Field::RhInside => self.rh_inside = Some(raw as u16),
+            Field::VSupply => self.v_supply = Some(U12::new(raw as u16)),
+            Field::TWaterSigned => self.t_water_signed = Some(I12::new(raw as i16)),
+            Field::Status | Field::Status2 => {
+                let shift = field.status_shift();
+                let bits = self.status.unwrap_or_default().bits() & !(0xFF << shift);
+                self.status = Some(StatusFlags(bits | (raw as u8 as u16) << shift));
+            }
+            Field::TWater2 => self.t_water_2 = Some(I12::new(raw as i16)),
+            Field::TWater3 => self.t_water_3 = Some(I12::new(raw as i16)),
+            Field::TWater4 => self.t_water_4 = Some(I12::new(raw as i16)),
+        }
+    }
+
+    /// Set the water temperature of the probe with the given index (in probe
+    /// order). The first probe uses the signed `t_water_signed` field.
+    ///
+    /// Panics if the index is not below `MAX_PROBES`.
+    pub fn set_water_temperature(&mut self, probe: usize, raw: I12) {
+        self.set_raw_value(Field::WATER_TEMPERATURES[probe], raw.as_i16() as i32);
+    }
+
+    /// Return the data mask, with a bit set for every available value.
+    ///
+    /// Bit `n` of the data mask is stored in bit `n % 7` of data mask byte
+    /// `n / 7`.
+    pub fn data_mask(&self) -> u32 {
+        let mut data_mask = 0u32;
+        for field in SCHEMA.iter() {
+            data_mask.set_bit(
+                field.field.mask_bit(),
+                self.raw_value(field.field).is_some(),
+            );
+        }
+        data_mask
+    }
+
     /// Encode the measurement into the given buffer.
     ///
+    /// The buffer must be large enough to hold the encoded measurement. A
+    /// buffer of `MAX_MSG_LEN` bytes can hold all known values.
+    ///
     /// Returns the number of bytes which should be sent
-    pub fn encode(&self, output: &mut EncodedMeasurement<[u8; MAX_MSG_LEN]>) -> usize {
-        let mut encoder = Encoder::new(output);
-        if let Some(t_water) = self.t_water {
-            encoder.encode(0, &t_water);
+    pub fn encode<B: Buffer>(&self, output: &mut EncodedMeasurement<B>) -> usize {
+        let mut encoder = Encoder::new(output, self.data_mask());
+        for field in SCHEMA.iter() {
+            if let Some(raw) = self.raw_value(field.field) {
+                encoder.encode(field, raw);
+            }
         }
-        if let Some(t_inside) = self.t_inside {
-            encoder.encode(1, &t_inside);
+        encoder.finish()
+    }
+
+    /// Decode a measurement from the given buffer.
+    ///
+    /// The buffer must contain exactly one encoded measurement, as returned
+    /// by [`encode`](Self::encode).
+    pub fn decode(input: &[u8]) -> Result<Self, DecodeError> {
+        let (message, length) = Self::decode_prefix(input)?;
+        if input.len() > length {
+            return Err(DecodeError::TooLong);
         }
-        if let Some(rh_inside) = self.rh_inside {
-            encoder.encode(2, &rh_inside);
+        Ok(message)
+    }
+
+    /// Decode a measurement from the start of the given buffer.
+    ///
+    /// Returns the measurement and the number of bytes it occupies.
+    fn decode_prefix(input: &[u8]) -> Result<(Self, usize), DecodeError> {
+        let mut decoder = Decoder::new(&input[..input.len().min(MAX_MSG_LEN)])?;
+        if decoder.data_mask & !Self::KNOWN_DATA_MASK_BITS != 0 {
+            return Err(DecodeError::UnsupportedDataMask(decoder.data_mask));
         }
-        if let Some(v_supply) = self.v_supply {
-            encoder.encode(3, &v_supply);
+        let mut message = Self::default();
+        for field in SCHEMA.iter() {
+            if let Some(raw) = decoder.decode(field)? {
+                message.set_raw_value(field.field, raw);
+            }
         }
-        encoder.finish()
+        Ok((message, decoder.finish()))
+    }
+}
+
+/// A temperature in degrees Celsius.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(transparent))]
+pub struct DegreesCelsius(pub f32);
+
+/// A relative humidity in percent (%RH).
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(transparent))]
+pub struct RelativeHumidity(pub f32);
+
+/// A voltage in millivolts.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(transparent))]
+pub struct Millivolts(pub u16);
+
+impl DegreesCelsius {
+    /// Convert a raw DS18B20 value (in 1/16 °C) to degrees Celsius.
+    pub fn from_ds18b20_raw(raw: U12) -> Self {
+        Self(Conversion::Ds18b20.apply(raw.as_u16() as i32))
+    }
+
+    /// Convert a signed raw DS18B20 value (in 1/16 °C) to degrees Celsius.
+    pub fn from_ds18b20_raw_signed(raw: I12) -> Self {
+        Self(Conversion::Ds18b20.apply(raw.as_i16() as i32))
+    }
+
+    /// Convert a raw SHTC3 temperature value to degrees Celsius.
+    pub fn from_shtc3_raw(raw: u16) -> Self {
+        Self(Conversion::Shtc3Temperature.apply(raw as i32))
+    }
+}
+
+impl RelativeHumidity {
+    /// Convert a raw SHTC3 humidity value to %RH.
+    pub fn from_shtc3_raw(raw: u16) -> Self {
+        Self(Conversion::Shtc3Humidity.apply(raw as i32))
+    }
+}
+
+impl Millivolts {
+    /// Convert a raw supply voltage value (in millivolts with 2 V offset) to
+    /// millivolts.
+    pub fn from_supply_raw(raw: U12) -> Self {
+        Self(Conversion::Supply.apply(raw.as_u16() as i32) as u16)
+    }
+}
+
+/// A measurement converted to physical units.
+///
+/// The conversions correspond to the ones documented in
+/// `docs/message-format.md`.
+#[derive(Copy, Clone, Default, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DecodedMeasurement {
+    pub t_water: Option<DegreesCelsius>,
+    pub t_inside: Option<DegreesCelsius>,
+    pub rh_inside: Option<RelativeHumidity>,
+    pub v_supply: Option<Millivolts>,
+    pub status: Option<StatusFlags>,
+    pub t_water_2: Option<DegreesCelsius>,
+    pub t_water_3: Option<DegreesCelsius>,
+    pub t_water_4: Option<DegreesCelsius>,
+}
+
+impl From<MeasurementMessage> for DecodedMeasurement {
+    fn from(message: MeasurementMessage) -> Self {
+        Self {
+            // Prefer the signed water temperature, fall back to the unsigned one
+            t_water: message
+                .t_water_signed
+                .map(DegreesCelsius::from_ds18b20_raw_signed)
+                .or_else(|| message.t_water.map(DegreesCelsius::from_ds18b20_raw)),
+            t_inside: message.t_inside.map(DegreesCelsius::from_shtc3_raw),
+            rh_inside: message.rh_inside.map(RelativeHumidity::from_shtc3_raw),
+            v_supply: message.v_supply.map(Millivolts::from_supply_raw),
+            status: message.status,
+            t_water_2: message
+                .t_water_2
+                .map(DegreesCelsius::from_ds18b20_raw_signed),
+            t_water_3: message
+                .t_water_3
+                .map(DegreesCelsius::from_ds18b20_raw_signed),
+            t_water_4: message
+                .t_water_4
+                .map(DegreesCelsius::from_ds18b20_raw_signed),
+        }
+    }
+}
+
+/// A measurement in the legacy format (FPort 1), sent by older sensors.
+///
+/// The format consists of four little endian `f32` values. A value that is
+/// not available is sent as NaN.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LegacyMeasurement {
+    /// Water temperature in °C
+    pub t_water: f32,
+    /// Temperature inside the housing in °C
+    pub t_inside: f32,
+    /// Relative humidity inside the housing in %RH
+    pub rh_inside: f32,
+    /// Supply voltage in V
+    pub v_supply: f32,
+}
+
+impl LegacyMeasurement {
+    /// Decode a measurement in the legacy format from the given buffer.
+    pub fn decode(input: &[u8]) -> Result<Self, DecodeError> {
+        if input.is_empty() {
+            return Err(DecodeError::Empty);
+        }
+        if input.len() < LEGACY_MSG_LEN {
+            return Err(DecodeError::TooShort);
+        }
+        if input.len() > LEGACY_MSG_LEN {
+            return Err(DecodeError::TooLong);
+        }
+        let read_f32 = |offset: usize| {
+            f32::from_le_bytes(
+                input[offset..offset + 4]
+                    .try_into()
+                    .expect("Reading f32 failed"),
+            )
+        };
+        Ok(Self {
+            t_water: read_f32(0),
+            t_inside: read_f32(4),
+            rh_inside: read_f32(8),
+            v_supply: read_f32(12),
+        })
+    }
+}
+
+impl From<LegacyMeasurement> for DecodedMeasurement {
+    fn from(message: LegacyMeasurement) -> Self {
+        let available = |value: f32| if value.is_nan() { None } else { Some(value) };
+        Self {
+            t_water: available(message.t_water).map(DegreesCelsius),
+            t_inside: available(message.t_inside).map(DegreesCelsius),
+            rh_inside: available(message.rh_inside).map(RelativeHumidity),
+            // Note: Float to int casts saturate, negative values result in 0
+            v_supply: available(message.v_supply)
+                .map(|volts| Millivolts((volts * 1000.0 + 0.5) as u16)),
+            status: None,
+            t_water_2: None,
+            t_water_3: None,
+            t_water_4: None,
+        }
+    }
+}
+
+/// Decode an uplink message, based on the FPort it was received on.
+///
+/// Both the legacy format (FPort 1) and the measurement format (FPort 2) are
+/// supported.
+pub fn decode_uplink(fport: u8, input: &[u8]) -> Result<DecodedMeasurement, DecodeError> {
+    match fport {
+        FPORT_LEGACY => LegacyMeasurement::decode(input).map(DecodedMeasurement::from),
+        FPORT_MEASUREMENT => MeasurementMessage::decode(input).map(DecodedMeasurement::from),
+        other => Err(DecodeError::UnsupportedFPort(other)),
+    }
+}
+
+/// A measurement in a batch message.
+#[derive(Copy, Clone, Default, Debug, PartialEq)]
+pub struct BatchRecord {
+    /// Number of seconds between the measurement and the transmission
+    pub age_seconds: u16,
+    pub measurement: MeasurementMessage,
+}
+
+/// Error returned when a batch message cannot hold another record.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BatchFull;
+
+/// Multiple measurements, sent together in a single uplink (FPort 3).
+///
+/// The message consists of a header byte with the number of records, followed
+/// by the records. Every record consists of its age in seconds (u16, big
+/// endian), followed by the measurement in the measurement format (FPort 2).
+#[derive(Copy, Clone, Default, Debug, PartialEq)]
+pub struct BatchMessage {
+    records: [BatchRecord; MAX_BATCH_SIZE],
+    count: usize,
+}
+
+impl BatchMessage {
+    /// Add a record to the batch.
+    pub fn push(&mut self, record: BatchRecord) -> Result<(), BatchFull> {
+        let slot = self.records.get_mut(self.count).ok_or(BatchFull)?;
+        *slot = record;
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Add a record to the batch, unless the encoded batch would then be
+    /// longer than `max_len` bytes.
+    pub fn push_within(&mut self, record: BatchRecord, max_len: usize) -> Result<(), BatchFull> {
+        let mut data = EncodedMeasurement([0u8; MAX_MSG_LEN]);
+        let record_len = BATCH_RECORD_HEADER_LEN + record.measurement.encode(&mut data);
+        if self.encoded_len() + record_len > max_len {
+            return Err(BatchFull);
+        }
+        self.push(record)
+    }
+
+    /// Return the length of the encoded batch.
+    pub fn encoded_len(&self) -> usize {
+        self.encode(&mut [0u8; MAX_BATCH_MSG_LEN])
+    }
+
+    /// Return the records in this batch.
+    pub fn records(&self) -> &[BatchRecord] {
+        &self.records[..self.count]
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Encode the batch into the given buffer.
+    ///
+    /// Returns the number of bytes which should be sent
+    pub fn encode(&self, output: &mut [u8; MAX_BATCH_MSG_LEN]) -> usize {
+        output[0] = self.count as u8;
+        let mut length = 1;
+        for record in self.records() {
+            output[length..length + BATCH_RECORD_HEADER_LEN]
+                .copy_from_slice(&record.age_seconds.to_be_bytes());
+            length += BATCH_RECORD_HEADER_LEN;
+            let data = &mut output[length..length + MAX_MSG_LEN];
+            data.fill(0);
+            length += record.measurement.encode(&mut EncodedMeasurement(data));
+        }
+        length
+    }
+
+    /// Decode a batch from the given buffer.
+    pub fn decode(input: &[u8]) -> Result<Self, DecodeError> {
+        let count = *input.first().ok_or(DecodeError::Empty)?;
+        if count as usize > MAX_BATCH_SIZE {
+            return Err(DecodeError::UnsupportedBatchSize(count));
+        }
+        let mut batch = Self::default();
+        let mut offset = 1;
+        for _ in 0..count {
+            // Note: The length is checked before indexing, decoding an empty
+            // measurement would result in `DecodeError::Empty`.
+            if input.len() <= offset + BATCH_RECORD_HEADER_LEN {
+                return Err(DecodeError::TooShort);
+            }
+            let age_seconds = u16::from_be_bytes([input[offset], input[offset + 1]]);
+            offset += BATCH_RECORD_HEADER_LEN;
+            let (measurement, length) = MeasurementMessage::decode_prefix(&input[offset..])?;
+            offset += length;
+            batch
+                .push(BatchRecord {
+                    age_seconds,
+                    measurement,
+                })
+                .expect("Batch size was checked");
+        }
+        if input.len() > offset {
+            return Err(DecodeError::TooLong);
+        }
+        Ok(batch)
+    }
+}
+
+/// Return the number of bytes needed to hold `bits` bits.
+const fn bits_to_bytes(bits: usize) -> usize {
+    (bits + 7) >> 3
+}
+
+/// Error returned when a series message cannot hold another value.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SeriesFull;
+
+/// Return the number of bits needed to represent `delta` in two's complement.
+fn delta_width(delta: i16) -> usize {
+    if delta == 0 {
+        return 0;
+    }
+    // Positive and negative values need the same number of bits as their
+    // magnitude (or its complement), plus one sign bit.
+    let magnitude = if delta < 0 { !delta } else { delta };
+    (16 - magnitude.leading_zeros() + 1) as usize
+}
+
+/// A series of water temperatures, sent in a single uplink (FPort 4).
+///
+/// Water temperature changes slowly, so successive values are mostly
+/// redundant. The first value is sent as signed 12 bit value, all following
+/// values as signed difference to their predecessor. The width of the deltas
+/// is chosen per message, as the smallest width that fits all deltas.
+///
+/// The message consists of the following fields (MSB first, without padding
+/// between the fields):
+///
+/// ```text
+/// |count (8 bits)|delta width (4 bits)|first value (12 bits)|deltas...|
+/// ```
+///
+/// An empty series consists of the count byte only.
+#[derive(Copy, Clone, Default, Debug, PartialEq)]
+pub struct TemperatureSeries {
+    values: [I12; MAX_SERIES_LEN],
+    count: usize,
+}
+
+impl TemperatureSeries {
+    /// Add a value to the series.
+    pub fn push(&mut self, value: I12) -> Result<(), SeriesFull> {
+        let slot = self.values.get_mut(self.count).ok_or(SeriesFull)?;
+        *slot = value;
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Return the values in this series.
+    pub fn values(&self) -> &[I12] {
+        &self.values[..self.count]
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Return an iterator over the differences between successive values.
+    fn deltas(&self) -> impl Iterator<Item = i16> + '_ {
+        self.values()
+            .windows(2)
+            .map(|pair| pair[1].as_i16() - pair[0].as_i16())
+    }
+
+    /// Return the smallest delta width that can represent all deltas.
+    pub fn delta_width(&self) -> usize {
+        self.deltas().map(delta_width).max().unwrap_or(0)
+    }
+
+    /// Encode the series into the given buffer.
+    ///
+    /// Returns the number of bytes which should be sent
+    pub fn encode(&self, output: &mut [u8; MAX_SERIES_MSG_LEN]) -> usize {
+        output.fill(0);
+        output[0] = self.count as u8;
+        let first = match self.values().first() {
+            Some(first) => first,
+            None => return 1,
+        };
+        let width = self.delta_width();
+        let mut output = EncodedMeasurement(&mut output[..]);
+        output.set_bit_range(11, 8, width as u8);
+        output.set_bit_range(23, 12, first.as_i16() as u16);
+        let mut bit_index = SERIES_HEADER_BITS;
+        if width > 0 {
+            for delta in self.deltas() {
+                output.set_bit_range(bit_index + width - 1, bit_index, delta as u16);
+                bit_index += width;
+            }
+        }
+        bits_to_bytes(bit_index)
+    }
+
+    /// Decode a series from the given buffer.
+    pub fn decode(input: &[u8]) -> Result<Self, DecodeError> {
+        let count = *input.first().ok_or(DecodeError::Empty)?;
+        if count as usize > MAX_SERIES_LEN {
+            return Err(DecodeError::UnsupportedSeriesLength(count));
+        }
+        let mut series = Self::default();
+        if count == 0 {
+            return match input.len() {
+                1 => Ok(series),
+                _ => Err(DecodeError::TooLong),
+            };
+        }
+        if input.len() < SERIES_HEADER_BITS / 8 {
+            return Err(DecodeError::TooShort);
+        }
+        let width = (input[1] >> 4) as usize;
+        if width > MAX_DELTA_WIDTH {
+            return Err(DecodeError::UnsupportedDeltaWidth(width as u8));
+        }
+        let bits = SERIES_HEADER_BITS + (count as usize - 1) * width;
+        let length = bits_to_bytes(bits);
+        if input.len() < length {
+            return Err(DecodeError::TooShort);
+        }
+        if input.len() > length {
+            return Err(DecodeError::TooLong);
+        }
+
+        let mut buf = [0u8; MAX_SERIES_MSG_LEN];
+        buf[..length].copy_from_slice(input);
+        let input = EncodedMeasurement(buf);
+        // Reading into a signed type sign-extends the value
+        let first: i16 = input.bit_range(23, 12);
+        let mut value = I12::new(first);
+        let mut bit_index = SERIES_HEADER_BITS;
+        series.push(value).expect("Series length was checked");
+        for _ in 1..count {
+            let delta: i16 = match width {
+                0 => 0,
+                // Reading into a signed type sign-extends the value
+                _ => input.bit_range(bit_index + width - 1, bit_index),
+            };
+            bit_index += width;
+            value = I12::new(value.as_i16() + delta);
+            series.push(value).expect("Series length was checked");
+        }
+        Ok(series)
     }
 }
 
@@ -111,7 +1144,7 @@ mod tests {
         let expeced_result = [0];
 
         let mut output = EncodedMeasurement([0u8; MAX_MSG_LEN]);
-        let length = input.encode(&mut output) as usize;
+        let length = input.encode(&mut output);
 
         assert_eq!(length, 1);
         assert_eq!(output.0[0..length], expeced_result);
@@ -126,7 +1159,7 @@ mod tests {
         let expeced_result = [1, 0b0000_0101, 0b1010_0000];
         let mut output = EncodedMeasurement([0u8; MAX_MSG_LEN]);
 
-        let length = input.encode(&mut output) as usize;
+        let length = input.encode(&mut output);
         println!("{:012b}", input.t_water.unwrap().0);
         for b in &output.0[1..length] {
             print!("{:08b} ", b);
@@ -143,6 +1176,11 @@ mod tests {
             t_inside: Some(0b1100_0011_1010_0101),
             rh_inside: Some(0b0011_1100_0101_1010),
             v_supply: Some(U12(0b1111_1010_0101)),
+            t_water_signed: None,
+            status: None,
+            t_water_2: None,
+            t_water_3: None,
+            t_water_4: None,
         };
         let expeced_result = [
             0x0F,
@@ -156,13 +1194,880 @@ mod tests {
         ];
         let mut output = EncodedMeasurement([0u8; MAX_MSG_LEN]);
 
-        let length = input.encode(&mut output) as usize;
+        let length = input.encode(&mut output);
         println!("{:012b}", input.t_water.unwrap().0);
         for b in &output.0[1..length] {
             print!("{:08b} ", b);
         }
         println!();
-        assert_eq!(length, MAX_MSG_LEN);
+        assert_eq!(length, 8);
         assert_eq!(output.0[0..length], expeced_result);
     }
+
+    #[test]
+    fn test_measurement_decode_empty() {
+        let decoded = MeasurementMessage::decode(&[0]).unwrap();
+        assert_eq!(decoded, MeasurementMessage::default());
+    }
+
+    #[test]
+    fn test_measurement_decode_t_water() {
+        let input = [1, 0b0000_0101, 0b1010_0000];
+        let decoded = MeasurementMessage::decode(&input).unwrap();
+        assert_eq!(
+            decoded,
+            MeasurementMessage {
+                t_water: Some(U12(0b0000_0101_1010)),
+                ..MeasurementMessage::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_measurement_decode_all() {
+        let input = [
+            0x0F,
+            0b0000_0101,
+            0b1010_1100,
+            0b0011_1010,
+            0b0101_0011,
+            0b1100_0101,
+            0b1010_1111,
+            0b1010_0101,
+        ];
+        let decoded = MeasurementMessage::decode(&input).unwrap();
+        assert_eq!(
+            decoded,
+            MeasurementMessage {
+                t_water: Some(U12(0b0000_0101_1010)),
+                t_inside: Some(0b1100_0011_1010_0101),
+                rh_inside: Some(0b0011_1100_0101_1010),
+                v_supply: Some(U12(0b1111_1010_0101)),
+                t_water_signed: None,
+                status: None,
+                t_water_2: None,
+                t_water_3: None,
+                t_water_4: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_measurement_roundtrip() {
+        let t_water = [None, Some(U12(0)), Some(U12(0x5A)), Some(U12(0xFFF))];
+        let t_inside = [None, Some(0), Some(0xC3A5)];
+        let rh_inside = [None, Some(0xFFFF), Some(0x3C5A)];
+        let v_supply = [None, Some(U12(0x123)), Some(U12(0xFA5))];
+        let t_water_signed = [None, Some(I12(-160)), Some(I12(-1)), Some(I12(1360))];
+        for &t_water in &t_water {
+            for &t_inside in &t_inside {
+                for &rh_inside in &rh_inside {
+                    for &v_supply in &v_supply {
+                        for &t_water_signed in &t_water_signed {
+                            let input = MeasurementMessage {
+                                t_water,
+                                t_inside,
+                                rh_inside,
+                                v_supply,
+                                t_water_signed,
+                                status: None,
+                                t_water_2: None,
+                                t_water_3: None,
+                                t_water_4: None,
+                            };
+                            let mut output = EncodedMeasurement([0u8; MAX_MSG_LEN]);
+                            let length = input.encode(&mut output);
+                            let decoded = MeasurementMessage::decode(&output.0[0..length]).unwrap();
+                            assert_eq!(decoded, input);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_measurement_decode_errors() {
+        assert_eq!(MeasurementMessage::decode(&[]), Err(DecodeError::Empty));
+        assert_eq!(
+            MeasurementMessage::decode(&[0b1000_0000, 0b0000_1000]),
+            Err(DecodeError::UnsupportedDataMask(1 << 10))
+        );
+        assert_eq!(
+            MeasurementMessage::decode(&[1, 0b0000_0101]),
+            Err(DecodeError::TooShort)
+        );
+        assert_eq!(
+            MeasurementMessage::decode(&[0x0F, 0, 0, 0, 0, 0, 0]),
+            Err(DecodeError::TooShort)
+        );
+        assert_eq!(
+            MeasurementMessage::decode(&[0, 0]),
+            Err(DecodeError::TooLong)
+        );
+        assert_eq!(
+            MeasurementMessage::decode(&[1, 0b0000_0101, 0b1010_0000, 0]),
+            Err(DecodeError::TooLong)
+        );
+    }
+
+    #[test]
+    fn test_decoded_measurement_conversion() {
+        let message = MeasurementMessage {
+            t_water: Some(U12(0b0001_0101_1010)),
+            t_inside: Some(0x6666),
+            rh_inside: Some(0x8000),
+            v_supply: Some(U12(1234)),
+            t_water_signed: None,
+            status: None,
+            t_water_2: None,
+            t_water_3: None,
+            t_water_4: None,
+        };
+        let decoded = DecodedMeasurement::from(message);
+        assert_eq!(decoded.t_water, Some(DegreesCelsius(21.625)));
+        let t_inside = decoded.t_inside.unwrap().0;
+        assert!((t_inside - 25.0).abs() < 0.01, "{}", t_inside);
+        assert_eq!(decoded.rh_inside, Some(RelativeHumidity(50.0)));
+        assert_eq!(decoded.v_supply, Some(Millivolts(3234)));
+    }
+
+    #[test]
+    fn test_decoded_measurement_conversion_limits() {
+        let min = DecodedMeasurement::from(MeasurementMessage {
+            t_water: Some(U12(0)),
+            t_inside: Some(0),
+            rh_inside: Some(0),
+            v_supply: Some(U12(0)),
+            t_water_signed: None,
+            status: None,
+            t_water_2: None,
+            t_water_3: None,
+            t_water_4: None,
+        });
+        assert_eq!(min.t_water, Some(DegreesCelsius(0.0)));
+        assert_eq!(min.t_inside, Some(DegreesCelsius(-45.0)));
+        assert_eq!(min.rh_inside, Some(RelativeHumidity(0.0)));
+        assert_eq!(min.v_supply, Some(Millivolts(2000)));
+
+        let max = DecodedMeasurement::from(MeasurementMessage {
+            t_water: Some(U12(0xFFF)),
+            t_inside: Some(0xFFFF),
+            rh_inside: Some(0xFFFF),
+            v_supply: Some(U12(0xFFF)),
+            t_water_signed: None,
+            status: None,
+            t_water_2: None,
+            t_water_3: None,
+            t_water_4: None,
+        });
+        assert_eq!(max.t_water, Some(DegreesCelsius(255.9375)));
+        assert!((max.t_inside.unwrap().0 - 130.0).abs() < 0.01);
+        assert!((max.rh_inside.unwrap().0 - 100.0).abs() < 0.01);
+        assert_eq!(max.v_supply, Some(Millivolts(6095)));
+
+        let empty = DecodedMeasurement::from(MeasurementMessage::default());
+        assert_eq!(empty, DecodedMeasurement::default());
+    }
+
+    fn legacy_frame(values: [f32; 4]) -> [u8; LEGACY_MSG_LEN] {
+        let mut frame = [0; LEGACY_MSG_LEN];
+        for (chunk, value) in frame.chunks_mut(4).zip(values.iter()) {
+            chunk.copy_from_slice(&value.to_le_bytes());
+        }
+        frame
+    }
+
+    #[test]
+    fn test_legacy_decode() {
+        let frame = legacy_frame([12.5, 21.25, 45.5, 3.3]);
+        let decoded = LegacyMeasurement::decode(&frame).unwrap();
+        assert_eq!(
+            decoded,
+            LegacyMeasurement {
+                t_water: 12.5,
+                t_inside: 21.25,
+                rh_inside: 45.5,
+                v_supply: 3.3,
+            }
+        );
+    }
+
+    #[test]
+    fn test_legacy_decode_errors() {
+        let frame = legacy_frame([0.0; 4]);
+        assert_eq!(LegacyMeasurement::decode(&[]), Err(DecodeError::Empty));
+        assert_eq!(
+            LegacyMeasurement::decode(&frame[..15]),
+            Err(DecodeError::TooShort)
+        );
+        let mut long = [0; LEGACY_MSG_LEN + 1];
+        long[..LEGACY_MSG_LEN].copy_from_slice(&frame);
+        assert_eq!(LegacyMeasurement::decode(&long), Err(DecodeError::TooLong));
+    }
+
+    #[test]
+    fn test_decode_uplink_legacy() {
+        let frame = legacy_frame([12.5, f32::NAN, 45.5, 3.3]);
+        let decoded = decode_uplink(FPORT_LEGACY, &frame).unwrap();
+        assert_eq!(
+            decoded,
+            DecodedMeasurement {
+                t_water: Some(DegreesCelsius(12.5)),
+                t_inside: None,
+                rh_inside: Some(RelativeHumidity(45.5)),
+                v_supply: Some(Millivolts(3300)),
+                status: None,
+                t_water_2: None,
+                t_water_3: None,
+                t_water_4: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_decode_uplink_measurement() {
+        let frame = [1, 0b0000_0101, 0b1010_0000];
+        let decoded = decode_uplink(FPORT_MEASUREMENT, &frame).unwrap();
+        assert_eq!(
+            decoded,
+            DecodedMeasurement {
+                t_water: Some(DegreesCelsius(5.625)),
+                ..DecodedMeasurement::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_decode_uplink_unsupported_fport() {
+        assert_eq!(
+            decode_uplink(42, &[0]),
+            Err(DecodeError::UnsupportedFPort(42))
+        );
+    }
+
+    #[test]
+    fn test_i12_new_clamps() {
+        assert_eq!(I12::new(-0x801), I12(-0x800));
+        assert_eq!(I12::new(-0x800), I12(-0x800));
+        assert_eq!(I12::new(0x7FF), I12(0x7FF));
+        assert_eq!(I12::new(0x800), I12(0x7FF));
+    }
+
+    #[test]
+    fn test_measurement_encode_t_water_signed() {
+        // Raw DS18B20 data (two's complement, 1/16 °C) and resulting frames
+        let cases = [
+            // -10 °C
+            (-160i16, [0b0001_0000, 0b1111_0110, 0b0000_0000]),
+            // -0.0625 °C
+            (-1, [0b0001_0000, 0b1111_1111, 0b1111_0000]),
+            // +85 °C
+            (0x0550, [0b0001_0000, 0b0101_0101, 0b0000_0000]),
+        ];
+        for (raw, expected_result) in &cases {
+            let input = MeasurementMessage {
+                t_water_signed: Some(I12::new(*raw)),
+                ..MeasurementMessage::default()
+            };
+            let mut output = EncodedMeasurement([0u8; MAX_MSG_LEN]);
+            let length = input.encode(&mut output);
+            assert_eq!(length, 3);
+            assert_eq!(&output.0[0..length], expected_result);
+            assert_eq!(MeasurementMessage::decode(expected_result).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn test_decoded_measurement_t_water_signed() {
+        let cases = [(-160i16, -10.0), (-1, -0.0625), (0x0550, 85.0)];
+        for &(raw, expected) in &cases {
+            let message = MeasurementMessage {
+                t_water_signed: Some(I12::new(raw)),
+                ..MeasurementMessage::default()
+            };
+            let decoded = DecodedMeasurement::from(message);
+            assert_eq!(decoded.t_water, Some(DegreesCelsius(expected)));
+        }
+    }
+
+    #[test]
+    fn test_decoded_measurement_prefers_t_water_signed() {
+        let message = MeasurementMessage {
+            t_water: Some(U12(0xFFF)),
+            t_water_signed: Some(I12(-16)),
+            ..MeasurementMessage::default()
+        };
+        let decoded = DecodedMeasurement::from(message);
+        assert_eq!(decoded.t_water, Some(DegreesCelsius(-1.0)));
+    }
+
+    #[test]
+    fn test_status_flags() {
+        let mut status = StatusFlags::default();
+        assert!(status.is_empty());
+        status.insert(StatusFlags::PANICKED);
+        status.insert(StatusFlags::LOW_BATTERY);
+        assert!(!status.is_empty());
+        assert!(status.contains(StatusFlags::PANICKED));
+        assert!(status.contains(StatusFlags::LOW_BATTERY));
+        assert!(!status.contains(StatusFlags::DS18B20_NOT_FOUND));
+        assert_eq!(status.bits(), 0b0101_0000);
+        assert_eq!(StatusFlags::from_bits(0b0101_0000), status);
+        status.insert(StatusFlags::DS18B20_POWER_ON_RESET);
+        assert_eq!(status.bits(), 0b1101_0000);
+        status.insert(StatusFlags::DS18B20_CRC_ERROR);
+        status.insert(StatusFlags::DS18B20_OUT_OF_RANGE);
+        assert_eq!(status.bits(), 0b11_1101_0000);
+    }
+
+    #[test]
+    fn test_measurement_encode_status() {
+        let mut status = StatusFlags::default();
+        status.insert(StatusFlags::DS18B20_NOT_FOUND);
+        status.insert(StatusFlags::SHTC3_CRC_ERROR);
+        let input = MeasurementMessage {
+            v_supply: Some(U12(0b1111_1010_0101)),
+            status: Some(status),
+            ..MeasurementMessage::default()
+        };
+        let expected_result = [0b0010_1000, 0b1111_1010, 0b0101_0000, 0b0101_0000];
+        let mut output = EncodedMeasurement([0u8; MAX_MSG_LEN]);
+        let length = input.encode(&mut output);
+        assert_eq!(&output.0[0..length], &expected_result);
+        assert_eq!(MeasurementMessage::decode(&expected_result).unwrap(), input);
+    }
+
+    #[test]
+    fn test_measurement_encode_status_2() {
+        let mut status = StatusFlags::default();
+        status.insert(StatusFlags::DS18B20_CRC_ERROR);
+        let input = MeasurementMessage {
+            status: Some(status),
+            ..MeasurementMessage::default()
+        };
+        // The status byte is sent even if only flags of the high byte are set
+        let expected_result = [0b1010_0000, 0b0000_0100, 0b0000_0000, 0b0000_0001];
+        let mut output = EncodedMeasurement([0u8; MAX_MSG_LEN]);
+        let length = input.encode(&mut output);
+        assert_eq!(&output.0[0..length], &expected_result);
+        assert_eq!(MeasurementMessage::decode(&expected_result).unwrap(), input);
+
+        // Without the status byte, only the flags of the high byte are set
+        let decoded = MeasurementMessage::decode(&[0b1000_0000, 0b0000_0100, 0b0000_0010]);
+        assert_eq!(
+            decoded.unwrap().status,
+            Some(StatusFlags::DS18B20_OUT_OF_RANGE)
+        );
+    }
+
+    #[test]
+    fn test_measurement_roundtrip_max_length() {
+        let input = MeasurementMessage {
+            t_water: Some(U12(0xFFF)),
+            t_inside: Some(0xFFFF),
+            rh_inside: Some(0xFFFF),
+            v_supply: Some(U12(0xFFF)),
+            t_water_signed: Some(I12(-1)),
+            status: Some(StatusFlags(0xFFFF)),
+            t_water_2: Some(I12(-1)),
+            t_water_3: Some(I12(-1)),
+            t_water_4: Some(I12(-1)),
+        };
+        let mut output = EncodedMeasurement([0u8; MAX_MSG_LEN]);
+        let length = input.encode(&mut output);
+        assert_eq!(length, MAX_MSG_LEN);
+        assert_eq!(MeasurementMessage::decode(&output.0).unwrap(), input);
+    }
+
+    #[test]
+    fn test_data_mask_single_byte() {
+        let mut output = EncodedMeasurement([0u8; MAX_MSG_LEN]);
+        let encoder = Encoder::new(&mut output, 0b0111_1111);
+        assert_eq!(encoder.finish(), 1);
+        assert_eq!(output.0[0], 0b0111_1111);
+    }
+
+    #[test]
+    fn test_data_mask_extension() {
+        let mut output = EncodedMeasurement([0u8; MAX_MSG_LEN]);
+        let data_mask = (1 << 16) | (1 << 7) | 1;
+        let encoder = Encoder::new(&mut output, data_mask);
+        assert_eq!(encoder.finish(), 3);
+        assert_eq!(output.0[0..3], [0b1000_0001, 0b1000_0001, 0b0000_0100]);
+
+        let decoder = Decoder::new(&output.0[0..3]).unwrap();
+        assert_eq!(decoder.data_mask, data_mask);
+        assert_eq!(decoder.bit_index, 24);
+    }
+
+    #[test]
+    fn test_measurement_decode_data_mask_extension() {
+        // Empty extension bytes are allowed
+        let input = [0b1000_0001, 0b1000_0000, 0, 0b0000_0101, 0b1010_0000];
+        let decoded = MeasurementMessage::decode(&input).unwrap();
+        assert_eq!(
+            decoded,
+            MeasurementMessage {
+                t_water: Some(U12(0b0000_0101_1010)),
+                ..MeasurementMessage::default()
+            }
+        );
+
+        // Unknown values in the extension are rejected
+        assert_eq!(
+            MeasurementMessage::decode(&[0b1000_0000, 0b0000_1000]),
+            Err(DecodeError::UnsupportedDataMask(1 << 10))
+        );
+
+        // The data mask must not be truncated
+        assert_eq!(
+            MeasurementMessage::decode(&[0b1000_0001]),
+            Err(DecodeError::TooShort)
+        );
+
+        // At most `MAX_DATA_MASK_LEN` data mask bytes are supported
+        assert_eq!(
+            MeasurementMessage::decode(&[0x80, 0x80, 0x80, 0x80, 0]),
+            Err(DecodeError::UnsupportedDataMask(0))
+        );
+    }
+
+    #[test]
+    fn test_measurement_multiple_probes() {
+        let mut input = MeasurementMessage::default();
+        for (probe, raw) in [-16, 320, 240, 80].iter().enumerate() {
+            input.set_water_temperature(probe, I12::new(*raw));
+        }
+        assert_eq!(input.t_water_signed, Some(I12(-16)));
+        assert_eq!(input.t_water_4, Some(I12(80)));
+
+        // Bits 4 and 6 in the first, bits 7 and 8 in the second data mask byte
+        let expected_result = [0b1101_0000, 0b0000_0011, 0xFF, 0x01, 0x40, 0x0F, 0x00, 0x50];
+        let mut output = EncodedMeasurement([0u8; MAX_MSG_LEN]);
+        let length = input.encode(&mut output);
+        assert_eq!(&output.0[0..length], &expected_result);
+        assert_eq!(MeasurementMessage::decode(&expected_result).unwrap(), input);
+
+        let decoded = DecodedMeasurement::from(input);
+        assert_eq!(decoded.t_water, Some(DegreesCelsius(-1.0)));
+        assert_eq!(decoded.t_water_2, Some(DegreesCelsius(20.0)));
+        assert_eq!(decoded.t_water_3, Some(DegreesCelsius(15.0)));
+        assert_eq!(decoded.t_water_4, Some(DegreesCelsius(5.0)));
+    }
+
+    #[test]
+    fn test_measurement_encode_generic_buffer() {
+        let input = MeasurementMessage {
+            t_water: Some(U12(0b0000_0101_1010)),
+            ..MeasurementMessage::default()
+        };
+        let expected_result = [1, 0b0000_0101, 0b1010_0000];
+
+        let mut array = EncodedMeasurement([0u8; 32]);
+        let length = input.encode(&mut array);
+        assert_eq!(array.0[0..length], expected_result);
+
+        let mut backing = [0u8; 3];
+        let mut slice = EncodedMeasurement(&mut backing[..]);
+        let length = input.encode(&mut slice);
+        assert_eq!(slice.0[0..length], expected_result);
+    }
+
+    fn example_batch() -> BatchMessage {
+        let mut batch = BatchMessage::default();
+        batch
+            .push(BatchRecord {
+                age_seconds: 900,
+                measurement: MeasurementMessage {
+                    t_water_signed: Some(I12(-1)),
+                    ..MeasurementMessage::default()
+                },
+            })
+            .unwrap();
+        batch
+            .push(BatchRecord {
+                age_seconds: 0,
+                measurement: MeasurementMessage {
+                    t_water_signed: Some(I12(0x0550)),
+                    v_supply: Some(U12(0xFA5)),
+                    ..MeasurementMessage::default()
+                },
+            })
+            .unwrap();
+        batch
+    }
+
+    #[test]
+    fn test_batch_encode() {
+        let mut output = [0u8; MAX_BATCH_MSG_LEN];
+        let length = example_batch().encode(&mut output);
+        let expected_result = [
+            2,
+            // Record 1: Age, data mask, T_water
+            0x03,
+            0x84,
+            0b0001_0000,
+            0b1111_1111,
+            0b1111_0000,
+            // Record 2: Age, data mask, V_supply, T_water
+            0x00,
+            0x00,
+            0b0001_1000,
+            0b1111_1010,
+            0b0101_0101,
+            0b0101_0000,
+        ];
+        assert_eq!(output[0..length], expected_result);
+    }
+
+    #[test]
+    fn test_batch_roundtrip() {
+        let batch = example_batch();
+        let mut output = [0u8; MAX_BATCH_MSG_LEN];
+        let length = batch.encode(&mut output);
+        assert_eq!(BatchMessage::decode(&output[0..length]).unwrap(), batch);
+    }
+
+    #[test]
+    fn test_batch_roundtrip_full() {
+        let mut batch = BatchMessage::default();
+        let record = BatchRecord {
+            age_seconds: 0xFFFF,
+            measurement: MeasurementMessage {
+                t_water: Some(U12(0xFFF)),
+                t_inside: Some(0xFFFF),
+                rh_inside: Some(0xFFFF),
+                v_supply: Some(U12(0xFFF)),
+                t_water_signed: Some(I12(-1)),
+                status: Some(StatusFlags(0xFFFF)),
+                t_water_2: Some(I12(-1)),
+                t_water_3: Some(I12(-1)),
+                t_water_4: Some(I12(-1)),
+            },
+        };
+        for _ in 0..MAX_BATCH_SIZE {
+            batch.push(record).unwrap();
+        }
+        assert_eq!(batch.push(record), Err(BatchFull));
+        assert_eq!(batch.len(), MAX_BATCH_SIZE);
+
+        let mut output = [0u8; MAX_BATCH_MSG_LEN];
+        let length = batch.encode(&mut output);
+        assert_eq!(length, MAX_BATCH_MSG_LEN);
+        assert_eq!(BatchMessage::decode(&output).unwrap(), batch);
+    }
+
+    #[test]
+    fn test_batch_push_within() {
+        let mut batch = BatchMessage::default();
+        let record = example_batch().records()[0];
+        // Header byte + 2 records of 5 bytes
+        assert_eq!(batch.push_within(record, 11), Ok(()));
+        assert_eq!(batch.push_within(record, 11), Ok(()));
+        assert_eq!(batch.encoded_len(), 11);
+        assert_eq!(batch.push_within(record, 11), Err(BatchFull));
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch.push_within(record, 16), Ok(()));
+    }
+
+    #[test]
+    fn test_max_batch_size() {
+        assert_eq!(max_payload_len(0), 51);
+        assert_eq!(max_payload_len(3), 115);
+        assert_eq!(max_payload_len(5), 222);
+        assert_eq!(max_batch_size(max_payload_len(0)), 2);
+        assert_eq!(max_batch_size(max_payload_len(3)), 6);
+        assert_eq!(max_batch_size(max_payload_len(4)), MAX_BATCH_SIZE);
+        assert_eq!(max_batch_size(0), 0);
+        assert_eq!(max_batch_size(MAX_BATCH_MSG_LEN), MAX_BATCH_SIZE);
+        assert_eq!(max_batch_size(MAX_BATCH_MSG_LEN - 1), MAX_BATCH_SIZE - 1);
+    }
+
+    #[test]
+    fn test_batch_decode_empty() {
+        let batch = BatchMessage::decode(&[0]).unwrap();
+        assert!(batch.is_empty());
+        assert_eq!(batch.records(), &[]);
+    }
+
+    #[test]
+    fn test_batch_decode_errors() {
+        let mut output = [0u8; MAX_BATCH_MSG_LEN];
+        let length = example_batch().encode(&mut output);
+
+        assert_eq!(BatchMessage::decode(&[]), Err(DecodeError::Empty));
+        assert_eq!(
+            BatchMessage::decode(&[MAX_BATCH_SIZE as u8 + 1]),
+            Err(DecodeError::UnsupportedBatchSize(MAX_BATCH_SIZE as u8 + 1))
+        );
+        for truncated in 1..length {
+            assert_eq!(
+                BatchMessage::decode(&output[0..truncated]),
+                Err(DecodeError::TooShort),
+                "Truncated to {} bytes",
+                truncated,
+            );
+        }
+        assert_eq!(
+            BatchMessage::decode(&output[0..length + 1]),
+            Err(DecodeError::TooLong)
+        );
+    }
+
+    #[test]
+    fn test_delta_width() {
+        assert_eq!(delta_width(0), 0);
+        assert_eq!(delta_width(-1), 1);
+        assert_eq!(delta_width(1), 2);
+        assert_eq!(delta_width(-2), 2);
+        assert_eq!(delta_width(2), 3);
+        assert_eq!(delta_width(-4), 3);
+        assert_eq!(delta_width(3), 3);
+        assert_eq!(delta_width(0xFFF), 13);
+        assert_eq!(delta_width(-0xFFF), 13);
+    }
+
+    fn series(values: &[i16]) -> TemperatureSeries {
+        let mut series = TemperatureSeries::default();
+        for value in values {
+            series.push(I12::new(*value)).unwrap();
+        }
+        series
+    }
+
+    #[test]
+    fn test_series_encode() {
+        // 21.5 °C, 21.5625 °C, 21.5 °C, 21.375 °C
+        let input = series(&[0x158, 0x159, 0x158, 0x156]);
+        assert_eq!(input.delta_width(), 2);
+        let mut output = [0u8; MAX_SERIES_MSG_LEN];
+        let length = input.encode(&mut output);
+        // Deltas: +1 (01), -1 (11), -2 (10)
+        assert_eq!(&output[0..length], &[0x04, 0x21, 0x58, 0b0111_1000]);
+    }
+
+    #[test]
+    fn test_series_encode_constant() {
+        let input = series(&[-160, -160, -160]);
+        assert_eq!(input.delta_width(), 0);
+        let mut output = [0u8; MAX_SERIES_MSG_LEN];
+        let length = input.encode(&mut output);
+        assert_eq!(&output[0..length], &[0x03, 0x0F, 0x60]);
+        assert_eq!(
+            TemperatureSeries::decode(&output[0..length]).unwrap(),
+            input
+        );
+    }
+
+    #[test]
+    fn test_series_encode_empty() {
+        let input = TemperatureSeries::default();
+        let mut output = [0u8; MAX_SERIES_MSG_LEN];
+        let length = input.encode(&mut output);
+        assert_eq!(&output[0..length], &[0x00]);
+        assert_eq!(
+            TemperatureSeries::decode(&output[0..length]).unwrap(),
+            input
+        );
+    }
+
+    #[test]
+    fn test_series_roundtrip() {
+        let inputs = [
+            series(&[0x158]),
+            series(&[0x158, 0x159, 0x158, 0x156]),
+            series(&[-5, -1, 0, 3, 2, -2]),
+            // Extreme deltas
+            series(&[I12::MIN, I12::MAX, I12::MIN]),
+            series(&[I12::MAX; MAX_SERIES_LEN]),
+        ];
+        for input in &inputs {
+            let mut output = [0u8; MAX_SERIES_MSG_LEN];
+            let length = input.encode(&mut output);
+            assert_eq!(
+                TemperatureSeries::decode(&output[0..length]).unwrap(),
+                *input
+            );
+        }
+    }
+
+    #[test]
+    fn test_series_full() {
+        let mut input = series(&[0; MAX_SERIES_LEN]);
+        assert_eq!(input.push(I12::new(0)), Err(SeriesFull));
+
+        // Largest possible message
+        let values: Vec<i16> = (0..MAX_SERIES_LEN)
+            .map(|i| if i % 2 == 0 { I12::MIN } else { I12::MAX })
+            .collect();
+        let input = series(&values);
+        assert_eq!(input.delta_width(), MAX_DELTA_WIDTH);
+        let mut output = [0u8; MAX_SERIES_MSG_LEN];
+        let length = input.encode(&mut output);
+        assert_eq!(length, MAX_SERIES_MSG_LEN);
+        assert_eq!(
+            TemperatureSeries::decode(&output[0..length]).unwrap(),
+            input
+        );
+    }
+
+    #[test]
+    fn test_series_one_hour() {
+        // An hour of 5-minute samples (13 values, including both ends)
+        // with realistic changes fits in a few bytes, far below the 51 byte
+        // limit of the slowest data rates.
+        let input = series(&[
+            0x158, 0x158, 0x159, 0x159, 0x15A, 0x159, 0x15B, 0x15C, 0x15C, 0x15D, 0x15B, 0x15C,
+            0x15C,
+        ]);
+        let mut output = [0u8; MAX_SERIES_MSG_LEN];
+        let length = input.encode(&mut output);
+        assert_eq!(input.delta_width(), 3);
+        assert_eq!(length, 8);
+        assert_eq!(
+            TemperatureSeries::decode(&output[0..length]).unwrap(),
+            input
+        );
+    }
+
+    #[test]
+    fn test_series_decode_errors() {
+        let mut output = [0u8; MAX_SERIES_MSG_LEN];
+        let length = series(&[0x158, 0x159, 0x158, 0x156]).encode(&mut output);
+
+        assert_eq!(TemperatureSeries::decode(&[]), Err(DecodeError::Empty));
+        assert_eq!(
+            TemperatureSeries::decode(&[MAX_SERIES_LEN as u8 + 1]),
+            Err(DecodeError::UnsupportedSeriesLength(
+                MAX_SERIES_LEN as u8 + 1
+            ))
+        );
+        assert_eq!(
+            TemperatureSeries::decode(&[0x02, 0xE0, 0x00, 0x00, 0x00]),
+            Err(DecodeError::UnsupportedDeltaWidth(14))
+        );
+        assert_eq!(
+            TemperatureSeries::decode(&[0x00, 0x00]),
+            Err(DecodeError::TooLong)
+        );
+        for truncated in 1..length {
+            assert_eq!(
+                TemperatureSeries::decode(&output[0..truncated]),
+                Err(DecodeError::TooShort),
+                "Truncated to {} bytes",
+                truncated
+            );
+        }
+        let mut too_long = output;
+        too_long[length] = 0;
+        assert_eq!(
+            TemperatureSeries::decode(&too_long[0..length + 1]),
+            Err(DecodeError::TooLong)
+        );
+    }
+
+    #[test]
+    fn test_schema_order() {
+        // The values are encoded in the order of the schema, which must match
+        // the order of the data mask bits.
+        for pair in SCHEMA.windows(2) {
+            assert!(pair[0].field.mask_bit() < pair[1].field.mask_bit());
+        }
+        assert_eq!(MeasurementMessage::KNOWN_DATA_MASK_BITS, 0b11_1111_1111);
+        assert_eq!(MAX_MSG_LEN, 17);
+    }
+
+    #[test]
+    fn test_schema_conversions() {
+        assert_eq!(Conversion::Ds18b20.apply(-160), -10.0);
+        assert_eq!(Conversion::Shtc3Temperature.apply(0x8000), 42.5);
+        assert_eq!(Conversion::Shtc3Humidity.apply(0x8000), 50.0);
+        assert_eq!(Conversion::Supply.apply(0x0FF), 2255.0);
+        assert_eq!(Conversion::Flags.apply(0b101), 5.0);
+    }
+
+    #[test]
+    fn test_schema_table_in_docs() {
+        let mut table = String::new();
+        write_schema_table(&mut table).unwrap();
+        let docs = include_str!("../../docs/message-format.md");
+        assert!(
+            docs.contains(&table),
+            "Schema table in docs/message-format.md is outdated, expected:\n{}",
+            table
+        );
+    }
+
+    /// Test the decoder against the golden test vectors, which are also used
+    /// to test the TTN payload formatter (see `ttn-formatter` in cli-utils).
+    #[test]
+    fn test_golden_vectors() {
+        let vectors: serde_json::Value =
+            serde_json::from_str(include_str!("../test-vectors/uplinks.json")).unwrap();
+        for vector in vectors.as_array().unwrap() {
+            let description = vector["description"].as_str().unwrap();
+            let fport = vector["fport"].as_u64().unwrap() as u8;
+            let hex = vector["payload"].as_str().unwrap();
+            let payload: Vec<u8> = (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+                .collect();
+
+            let result = decode_uplink(fport, &payload);
+            if let Some(error) = vector.get("error") {
+                assert_eq!(
+                    result.unwrap_err().to_string(),
+                    error.as_str().unwrap(),
+                    "{}",
+                    description
+                );
+                continue;
+            }
+
+            let decoded = result.unwrap();
+            let expected = &vector["expected"];
+            // Note: The expected values are f32 values, so they can be
+            // compared exactly after converting back to f32.
+            let float = |key: &str| expected[key].as_f64().map(|value| value as f32);
+            let int = |key: &str| expected[key].as_u64();
+            assert_eq!(
+                decoded.t_water.map(|t| t.0),
+                float("t_water"),
+                "{}",
+                description
+            );
+            assert_eq!(
+                decoded.t_inside.map(|t| t.0),
+                float("t_inside"),
+                "{}",
+                description
+            );
+            assert_eq!(
+                decoded.rh_inside.map(|rh| rh.0),
+                float("rh_inside"),
+                "{}",
+                description
+            );
+            assert_eq!(
+                decoded.v_supply.map(|v| v.0 as u64),
+                int("v_supply"),
+                "{}",
+                description
+            );
+            assert_eq!(
+                decoded.status.map(|status| status.bits() as u64),
+                int("status"),
+                "{}",
+                description
+            );
+            let t_water_probes = [decoded.t_water_2, decoded.t_water_3, decoded.t_water_4];
+            for (t, key) in t_water_probes
+                .iter()
+                .zip(["t_water_2", "t_water_3", "t_water_4"].iter())
+            {
+                assert_eq!(t.map(|t| t.0), float(key), "{}", description);
+            }
+        }
+    }
 }
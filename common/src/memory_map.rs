@@ -0,0 +1,113 @@
+//! Map of the data EEPROM regions.
+//!
+//! Everything that must survive standby mode or a reset is stored in the data
+//! EEPROM. The regions are defined here, so that they can be checked for
+//! overlap and bounds at compile time.
+//!
+//! ```text
+//! 0x0808_0000 | Config slots (see `config_slots`)              |
+//! 0x0808_0200 | Panic store                                    |
+//! 0x0808_0280 | Command acknowledgement store                  |
+//! 0x0808_0300 | Sample buffer                                  |
+//! 0x0808_0400 | Unused                                         |
+//! ...         |                                                |
+//! 0x0808_1800 | End of the data EEPROM                         |
+//! ```
+
+/// Start address of the data EEPROM
+pub const EEPROM_START: usize = 0x0808_0000;
+
+/// End address (exclusive) of the data EEPROM (6 KiB on the STM32L071KB)
+pub const EEPROM_END: usize = 0x0808_1800;
+
+/// A region of the data EEPROM.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Region {
+    /// Start address (word aligned)
+    pub start: usize,
+    /// Length in bytes (whole words)
+    pub len: usize,
+}
+
+impl Region {
+    /// Return the end address (exclusive) of the region.
+    pub const fn end(&self) -> usize {
+        self.start + self.len
+    }
+}
+
+/// Configuration slots A and B
+pub const CONFIG_SLOTS: Region = Region {
+    start: EEPROM_START,
+    len: 0x200,
+};
+
+/// Panic record that has not been transmitted yet
+pub const PANIC_STORE: Region = Region {
+    start: CONFIG_SLOTS.end(),
+    len: 0x80,
+};
+
+/// Command acknowledgement that has not been transmitted yet
+pub const ACK_STORE: Region = Region {
+    start: PANIC_STORE.end(),
+    len: 0x80,
+};
+
+/// Measurement samples that are sent in a batch
+pub const SAMPLE_BUFFER: Region = Region {
+    start: ACK_STORE.end(),
+    len: 0x100,
+};
+
+/// All regions, ordered by address.
+///
+/// Compilation fails if the regions overlap or exceed the data EEPROM.
+pub const REGIONS: [Region; 4] = {
+    let regions = [CONFIG_SLOTS, PANIC_STORE, ACK_STORE, SAMPLE_BUFFER];
+    assert!(regions_valid(&regions), "Invalid EEPROM memory map");
+    regions
+};
+
+/// Return whether the regions are word aligned, ordered by address, do not
+/// overlap and lie within the data EEPROM.
+const fn regions_valid(regions: &[Region]) -> bool {
+    let mut previous_end = EEPROM_START;
+    let mut i = 0;
+    while i < regions.len() {
+        let region = regions[i];
+        let unaligned = (region.start | region.len) & 0b11 != 0;
+        if unaligned || region.start < previous_end {
+            return false;
+        }
+        previous_end = region.end();
+        i += 1;
+    }
+    previous_end <= EEPROM_END
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_regions_valid() {
+        assert!(regions_valid(&REGIONS));
+
+        let overlapping = Region {
+            start: PANIC_STORE.start + 4,
+            len: 4,
+        };
+        assert!(!regions_valid(&[PANIC_STORE, overlapping]));
+        let unaligned = Region {
+            start: PANIC_STORE.start + 2,
+            len: 4,
+        };
+        assert!(!regions_valid(&[unaligned]));
+        let out_of_bounds = Region {
+            start: EEPROM_END - 4,
+            len: 8,
+        };
+        assert!(!regions_valid(&[out_of_bounds]));
+    }
+}
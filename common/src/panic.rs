@@ -0,0 +1,183 @@
+//! Panic record, reporting a persisted panic message over LoRaWAN.
+//!
+//! A panic message is usually too long to be sent in a single uplink.
+//! Therefore only a hash of the full message and the first bytes of the panic
+//! location (`file:line:column`) are sent.
+
+use core::{convert::TryInto, str};
+
+use crate::measurement::DecodeError;
+
+/// FPort of the panic record.
+pub const FPORT_PANIC: u8 = 6;
+
+/// Maximum number of location bytes in a panic record.
+pub const MAX_LOCATION_LEN: usize = 32;
+
+/// Length of the hash in a panic record.
+const HASH_LEN: usize = 4;
+
+/// Maximum length of an encoded panic record.
+pub const MAX_PANIC_MSG_LEN: usize = HASH_LEN + MAX_LOCATION_LEN;
+
+/// Calculate the 32 bit FNV-1a hash of the given bytes.
+pub fn fnv1a32(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// Extract the location (`file:line:column`) from a panic message.
+///
+/// Both the format `panicked at 'message', src/main.rs:1:2` and the format
+/// `panicked at src/main.rs:1:2:\nmessage` are supported. If no location is
+/// found, the whole message is returned.
+pub fn panic_location(message: &str) -> &str {
+    let message = message.trim_end();
+    if let Some(index) = message.rfind("', ") {
+        return &message[index + 3..];
+    }
+    if let Some(rest) = message.strip_prefix("panicked at ") {
+        return rest.lines().next().unwrap_or("").trim_end_matches(':');
+    }
+    message
+}
+
+/// A truncated panic message (FPort 6).
+///
+/// Format: The FNV-1a hash of the full panic message (u32, big endian),
+/// followed by up to 32 bytes of the panic location.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PanicRecord {
+    /// FNV-1a hash of the full panic message
+    pub hash: u32,
+    location: [u8; MAX_LOCATION_LEN],
+    location_len: usize,
+}
+
+impl PanicRecord {
+    /// Create a panic record from a full panic message.
+    pub fn from_message(message: &str) -> Self {
+        let mut record = Self {
+            hash: fnv1a32(message.as_bytes()),
+            location: [0; MAX_LOCATION_LEN],
+            location_len: 0,
+        };
+        record.set_location(panic_location(message).as_bytes());
+        record
+    }
+
+    fn set_location(&mut self, location: &[u8]) {
+        self.location_len = location.len().min(MAX_LOCATION_LEN);
+        self.location[..self.location_len].copy_from_slice(&location[..self.location_len]);
+    }
+
+    /// Return the (possibly truncated) panic location.
+    ///
+    /// If the location was truncated within a multi-byte character, the
+    /// incomplete character is omitted.
+    pub fn location(&self) -> &str {
+        let bytes = &self.location[..self.location_len];
+        match str::from_utf8(bytes) {
+            Ok(location) => location,
+            Err(e) => str::from_utf8(&bytes[..e.valid_up_to()]).unwrap_or(""),
+        }
+    }
+
+    /// Encode the panic record into the given buffer.
+    ///
+    /// Returns the number of bytes which should be sent
+    pub fn encode(&self, output: &mut [u8; MAX_PANIC_MSG_LEN]) -> usize {
+        output[..HASH_LEN].copy_from_slice(&self.hash.to_be_bytes());
+        output[HASH_LEN..HASH_LEN + self.location_len]
+            .copy_from_slice(&self.location[..self.location_len]);
+        HASH_LEN + self.location_len
+    }
+
+    /// Decode a panic record.
+    pub fn decode(input: &[u8]) -> Result<Self, DecodeError> {
+        if input.is_empty() {
+            return Err(DecodeError::Empty);
+        }
+        if input.len() < HASH_LEN {
+            return Err(DecodeError::TooShort);
+        }
+        if input.len() > MAX_PANIC_MSG_LEN {
+            return Err(DecodeError::TooLong);
+        }
+        let mut record = Self {
+            hash: u32::from_be_bytes(input[..HASH_LEN].try_into().unwrap()),
+            location: [0; MAX_LOCATION_LEN],
+            location_len: 0,
+        };
+        record.set_location(&input[HASH_LEN..]);
+        Ok(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fnv1a32() {
+        // Test vectors from the FNV reference implementation
+        assert_eq!(fnv1a32(b""), 0x811c_9dc5);
+        assert_eq!(fnv1a32(b"a"), 0xe40c_292c);
+        assert_eq!(fnv1a32(b"foobar"), 0xbf9c_f968);
+    }
+
+    #[test]
+    fn test_panic_location() {
+        assert_eq!(
+            panic_location("panicked at 'Could not set dev addr', src/main.rs:504:18\n"),
+            "src/main.rs:504:18"
+        );
+        assert_eq!(
+            panic_location("panicked at src/main.rs:504:18:\nCould not set dev addr"),
+            "src/main.rs:504:18"
+        );
+        assert_eq!(panic_location("something else"), "something else");
+    }
+
+    #[test]
+    fn test_panic_record_roundtrip() {
+        let message = "panicked at 'Could not set dev addr', src/main.rs:504:18";
+        let record = PanicRecord::from_message(message);
+        assert_eq!(record.hash, fnv1a32(message.as_bytes()));
+        assert_eq!(record.location(), "src/main.rs:504:18");
+
+        let mut output = [0; MAX_PANIC_MSG_LEN];
+        let length = record.encode(&mut output);
+        assert_eq!(length, 4 + 18);
+        assert_eq!(&output[..4], &record.hash.to_be_bytes());
+        assert_eq!(&output[4..length], b"src/main.rs:504:18");
+        assert_eq!(PanicRecord::decode(&output[..length]).unwrap(), record);
+    }
+
+    #[test]
+    fn test_panic_record_truncated() {
+        let message =
+            "panicked at 'oops', /home/user/.cargo/registry/src/some-crate-0.1.0/src/lib.rs:42:1";
+        let record = PanicRecord::from_message(message);
+        assert_eq!(record.location(), "/home/user/.cargo/registry/src/s");
+
+        // Truncated within a multi-byte character
+        let record =
+            PanicRecord::from_message("panicked at 'oops', src/äöü/ääääääääääääääääääää.rs:1:1");
+        assert_eq!(record.location(), "src/äöü/ääääääääää");
+    }
+
+    #[test]
+    fn test_panic_record_decode_errors() {
+        assert_eq!(PanicRecord::decode(&[]), Err(DecodeError::Empty));
+        assert_eq!(PanicRecord::decode(&[1, 2, 3]), Err(DecodeError::TooShort));
+        assert_eq!(
+            PanicRecord::decode(&[0; MAX_PANIC_MSG_LEN + 1]),
+            Err(DecodeError::TooLong)
+        );
+        let record = PanicRecord::decode(&[1, 2, 3, 4]).unwrap();
+        assert_eq!(record.hash, 0x0102_0304);
+        assert_eq!(record.location(), "");
+    }
+}
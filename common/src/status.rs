@@ -0,0 +1,281 @@
+//! Status message, sent once after a cold boot.
+//!
+//! The status message tells the backend which firmware, hardware revision and
+//! config a device is running, and which water temperature probes are
+//! connected.
+
+use core::{convert::TryInto, fmt};
+
+use crate::{config::MAX_PROBES, measurement::DecodeError, panic::fnv1a32};
+
+/// FPort of the status message.
+pub const FPORT_STATUS: u8 = 5;
+
+/// Version of the status message format.
+pub const STATUS_MSG_VERSION: u8 = 2;
+
+/// Length of an encoded status message.
+pub const STATUS_MSG_LEN: usize = 14;
+
+/// Length of an encoded status message in version 1 (without probe ID).
+const STATUS_MSG_V1_LEN: usize = 10;
+
+/// Calculate the probe ID: The 32 bit FNV-1a hash of the 64 bit ROM addresses
+/// (little endian) of the DS18B20 probes, in the order of their positions.
+///
+/// Up to `MAX_PROBES` addresses are hashed. Returns `None` if there are no
+/// probes.
+pub fn probe_id<I: IntoIterator<Item = u64>>(rom_addresses: I) -> Option<u32> {
+    let mut bytes = [0; 8 * MAX_PROBES];
+    let mut len = 0;
+    for address in rom_addresses.into_iter().take(MAX_PROBES) {
+        bytes[len..len + 8].copy_from_slice(&address.to_le_bytes());
+        len += 8;
+    }
+    if len == 0 {
+        None
+    } else {
+        Some(fnv1a32(&bytes[..len]))
+    }
+}
+
+/// A version number in the form `major.minor.patch`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Version {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
+}
+
+impl Version {
+    /// Parse a version string like `1.0.5`.
+    ///
+    /// A pre-release or build suffix (e.g. `0.1.0-dev`) is ignored. Returns
+    /// `None` if the string is not a valid version or if a component does not
+    /// fit into a byte.
+    pub fn parse(version: &str) -> Option<Self> {
+        let version = version.split(&['-', '+'][..]).next()?;
+        let mut parts = version.split('.').map(|part| part.parse::<u8>().ok());
+        let version = Self {
+            major: parts.next()??,
+            minor: parts.next()??,
+            patch: parts.next()??,
+        };
+        match parts.next() {
+            Some(_) => None,
+            None => Some(version),
+        }
+    }
+
+    /// Encode a version, `None` is encoded as `0.0.0`.
+    fn encode(version: Option<Self>) -> [u8; 3] {
+        match version {
+            Some(v) => [v.major, v.minor, v.patch],
+            None => [0; 3],
+        }
+    }
+
+    /// Decode a version, `0.0.0` is decoded as `None`.
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        match bytes {
+            [0, 0, 0] => None,
+            [major, minor, patch] => Some(Self {
+                major: *major,
+                minor: *minor,
+                patch: *patch,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Status message, sent once after a cold boot (FPort 5).
+///
+/// Format (all fields are single bytes, versions consist of major, minor and
+/// patch bytes):
+///
+/// ```text
+/// |msg version|firmware version|hardware version|RN2483 version|config version|flags|probe ID|
+/// ```
+///
+/// Unknown versions are encoded as `0.0.0`. Bit 0 of the flags byte is set if
+/// a panic message was found, the other bits are reserved. The probe ID (see
+/// [`probe_id`]) is a big endian u32, `0` if no probe was found.
+///
+/// Version 1 messages (without probe ID) can still be decoded.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct StatusMessage {
+    pub firmware_version: Option<Version>,
+    /// Raw hardware version, as detected by the version pins
+    pub hardware_version: u8,
+    pub rn2483_version: Option<Version>,
+    pub config_version: u8,
+    /// Whether a panic message from the previous boot was found
+    pub panicked: bool,
+    /// Hash of the ROM addresses of the connected DS18B20 probes
+    pub probe_id: Option<u32>,
+}
+
+impl StatusMessage {
+    /// Encode the status message.
+    pub fn encode(&self) -> [u8; STATUS_MSG_LEN] {
+        let mut output = [0; STATUS_MSG_LEN];
+        output[0] = STATUS_MSG_VERSION;
+        output[1..4].copy_from_slice(&Version::encode(self.firmware_version));
+        output[4] = self.hardware_version;
+        output[5..8].copy_from_slice(&Version::encode(self.rn2483_version));
+        output[8] = self.config_version;
+        output[9] = self.panicked as u8;
+        output[10..14].copy_from_slice(&self.probe_id.unwrap_or(0).to_be_bytes());
+        output
+    }
+
+    /// Decode a status message.
+    pub fn decode(input: &[u8]) -> Result<Self, DecodeError> {
+        let version = *input.first().ok_or(DecodeError::Empty)?;
+        let len = match version {
+            1 => STATUS_MSG_V1_LEN,
+            STATUS_MSG_VERSION => STATUS_MSG_LEN,
+            _ => return Err(DecodeError::UnsupportedVersion(version)),
+        };
+        if input.len() < len {
+            return Err(DecodeError::TooShort);
+        }
+        if input.len() > len {
+            return Err(DecodeError::TooLong);
+        }
+        let probe_id = match input.get(10..14) {
+            Some(bytes) => u32::from_be_bytes(bytes.try_into().unwrap()),
+            None => 0,
+        };
+        Ok(Self {
+            firmware_version: Version::decode(&input[1..4]),
+            hardware_version: input[4],
+            rn2483_version: Version::decode(&input[5..8]),
+            config_version: input[8],
+            panicked: input[9] & 0x01 != 0,
+            probe_id: if probe_id == 0 { None } else { Some(probe_id) },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(major: u8, minor: u8, patch: u8) -> Option<Version> {
+        Some(Version {
+            major,
+            minor,
+            patch,
+        })
+    }
+
+    #[test]
+    fn test_version_parse() {
+        assert_eq!(Version::parse("1.0.5"), version(1, 0, 5));
+        assert_eq!(Version::parse("0.1.0-dev"), version(0, 1, 0));
+        assert_eq!(Version::parse("0.1.0+abc"), version(0, 1, 0));
+        assert_eq!(Version::parse("1.0"), None);
+        assert_eq!(Version::parse("1.0.5.1"), None);
+        assert_eq!(Version::parse("1.0.256"), None);
+        assert_eq!(Version::parse("RN2483"), None);
+        assert_eq!(Version::parse(""), None);
+    }
+
+    #[test]
+    fn test_version_display() {
+        assert_eq!(version(1, 0, 5).unwrap().to_string(), "1.0.5");
+    }
+
+    #[test]
+    fn test_status_roundtrip() {
+        let message = StatusMessage {
+            firmware_version: version(0, 1, 0),
+            hardware_version: 0,
+            rn2483_version: version(1, 0, 5),
+            config_version: 1,
+            panicked: true,
+            probe_id: Some(0x1234_5678),
+        };
+        let encoded = message.encode();
+        assert_eq!(
+            encoded,
+            [2, 0, 1, 0, 0, 1, 0, 5, 1, 1, 0x12, 0x34, 0x56, 0x78]
+        );
+        assert_eq!(StatusMessage::decode(&encoded).unwrap(), message);
+    }
+
+    #[test]
+    fn test_status_unknown_versions() {
+        let message = StatusMessage {
+            firmware_version: None,
+            hardware_version: 3,
+            rn2483_version: None,
+            config_version: 1,
+            panicked: false,
+            probe_id: None,
+        };
+        let encoded = message.encode();
+        assert_eq!(encoded, [2, 0, 0, 0, 3, 0, 0, 0, 1, 0, 0, 0, 0, 0]);
+        assert_eq!(StatusMessage::decode(&encoded).unwrap(), message);
+    }
+
+    #[test]
+    fn test_status_decode_v1() {
+        assert_eq!(
+            StatusMessage::decode(&[1, 0, 1, 0, 0, 1, 0, 5, 1, 1]).unwrap(),
+            StatusMessage {
+                firmware_version: version(0, 1, 0),
+                hardware_version: 0,
+                rn2483_version: version(1, 0, 5),
+                config_version: 1,
+                panicked: true,
+                probe_id: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_probe_id() {
+        let a = 0x9D00_0007_1CF4_4328;
+        let b = 0x3200_0006_A1B2_C328;
+        assert_eq!(probe_id(core::iter::empty()), None);
+        assert_eq!(probe_id([a]), Some(fnv1a32(&a.to_le_bytes())));
+        // Stable for the same probes, but depends on their order
+        assert_eq!(probe_id([a, b]), probe_id([a, b]));
+        assert_ne!(probe_id([a, b]), probe_id([b, a]));
+        assert_ne!(probe_id([a, b]), probe_id([a]));
+        // Only the first `MAX_PROBES` addresses are hashed
+        assert_eq!(probe_id([a, b, a, b, a]), probe_id([a, b, a, b]));
+    }
+
+    #[test]
+    fn test_status_decode_errors() {
+        assert_eq!(StatusMessage::decode(&[]), Err(DecodeError::Empty));
+        assert_eq!(
+            StatusMessage::decode(&[3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
+            Err(DecodeError::UnsupportedVersion(3))
+        );
+        assert_eq!(
+            StatusMessage::decode(&[1, 0, 1, 0]),
+            Err(DecodeError::TooShort)
+        );
+        assert_eq!(
+            StatusMessage::decode(&[1, 0, 1, 0, 0, 1, 0, 5, 1, 1, 0]),
+            Err(DecodeError::TooLong)
+        );
+        assert_eq!(
+            StatusMessage::decode(&[2, 0, 1, 0, 0, 1, 0, 5, 1, 1]),
+            Err(DecodeError::TooShort)
+        );
+    }
+}
@@ -0,0 +1,83 @@
+//! Prints the temperatures of all DS18B20 probes on the one-wire bus to the
+//! serial port.
+#![no_main]
+#![no_std]
+
+use panic_persist as _;
+
+use core::fmt::Write;
+
+use cortex_m_rt::entry;
+use embedded_time::rate::Baud;
+use hal::serial;
+use one_wire_bus::OneWire;
+use stm32l0xx_hal as hal;
+use stm32l0xx_hal::prelude::*;
+
+use gfroerli_common::config::Ds18b20Resolution;
+use gfroerli_firmware::ds18b20::{Ds18b20, Ds18b20Reading};
+
+#[entry]
+fn main() -> ! {
+    let p = cortex_m::Peripherals::take().unwrap();
+    let dp = stm32l0xx_hal::pac::Peripherals::take().unwrap();
+
+    let syst = p.SYST;
+    let mut rcc = dp.RCC.freeze(hal::rcc::Config::hsi16());
+    let mut delay = hal::delay::Delay::new(syst, rcc.clocks);
+
+    let gpiob = dp.GPIOB.split(&mut rcc);
+    let gpioa = dp.GPIOA.split(&mut rcc);
+
+    let mut serial = hal::serial::Serial::usart1(
+        dp.USART1,
+        gpiob.pb6.into_floating_input(),
+        gpiob.pb7.into_floating_input(),
+        serial::Config {
+            baudrate: Baud(57_600),
+            wordlength: serial::WordLength::DataBits8,
+            parity: serial::Parity::ParityNone,
+            stopbits: serial::StopBits::STOP1,
+        },
+        &mut rcc,
+    )
+    .unwrap();
+
+    writeln!(serial, "Starting ds18b20 example").unwrap();
+
+    let mut one_wire = OneWire::new(gpioa.pa6.into_open_drain_output()).unwrap();
+    let probes = Ds18b20::find_all(&mut one_wire, &mut delay).unwrap();
+    for probe in probes.as_slice() {
+        writeln!(serial, "Found DS18B20 {:012X}", probe.serial()).unwrap();
+    }
+
+    let resolution = Ds18b20Resolution::Bits12;
+    loop {
+        Ds18b20::start_measurement_all(&mut one_wire, &mut delay).unwrap();
+        match Ds18b20::wait_for_conversion(
+            &mut one_wire,
+            resolution.conversion_time_ms() * 2,
+            &mut delay,
+        ) {
+            Ok(()) => {
+                for probe in probes.as_slice() {
+                    match probe.read_temperature(&mut one_wire, &mut delay) {
+                        Ok(Ds18b20Reading::Valid(raw)) => writeln!(
+                            serial,
+                            "{:012X}: {:.4}°C",
+                            probe.serial(),
+                            raw as f32 / 16.0
+                        )
+                        .unwrap(),
+                        Ok(reading) => {
+                            writeln!(serial, "{:012X}: {:?}", probe.serial(), reading).unwrap()
+                        }
+                        Err(e) => writeln!(serial, "{:012X}: {:?}", probe.serial(), e).unwrap(),
+                    }
+                }
+            }
+            Err(e) => writeln!(serial, "Conversion failed: {:?}", e).unwrap(),
+        }
+        delay.delay_ms(1_000u16);
+    }
+}
@@ -0,0 +1,64 @@
+//! Persistent storage for a command acknowledgement that has not been
+//! transmitted yet.
+//!
+//! Downlink commands are received in reply to an uplink, right before the
+//! device enters standby mode. The acknowledgement is sent with the next
+//! uplink, so it must survive standby.
+//!
+//! ## Memory Map
+//!
+//! Offsets are relative to the start of the
+//! [`ACK_STORE`](gfroerli_common::memory_map::ACK_STORE) region.
+//!
+//! ```text
+//!             0           8          16          24          32
+//!             +-----------+-----------+-----------+-----------+
+//! 0x0000      | Marker    | Opcode    | Result    | Reserved  |
+//!             +-----------+-----------+-----------+-----------+
+//! ```
+//!
+//! The marker is `0xAC` if an acknowledgement is pending, and `0x00`
+//! otherwise.
+
+use gfroerli_common::{command::CommandAck, memory_map::ACK_STORE};
+use stm32l0xx_hal::flash::{self, FLASH};
+
+/// Address of the pending acknowledgement in EEPROM
+pub const BASE_ADDR: usize = ACK_STORE.start;
+
+/// Marker of a pending acknowledgement
+const MARKER: u8 = 0xAC;
+
+/// Command acknowledgement storage in EEPROM.
+pub struct AckStore<'a> {
+    flash: &'a mut FLASH,
+}
+
+impl<'a> AckStore<'a> {
+    pub fn new(flash: &'a mut FLASH) -> Self {
+        Self { flash }
+    }
+
+    /// Return the pending acknowledgement, if any.
+    pub fn load(&self) -> Option<CommandAck> {
+        // Note(unsafe): Read with no side effects. This is safe since we hold
+        // a mutable reference to the FLASH peripheral.
+        let word = unsafe { core::ptr::read_volatile(BASE_ADDR as *const u32) };
+        match word.to_le_bytes() {
+            [MARKER, opcode, result, _] => CommandAck::decode(&[opcode, result]).ok(),
+            _ => None,
+        }
+    }
+
+    /// Store a pending acknowledgement, replacing the stored one.
+    pub fn store(&mut self, ack: &CommandAck) -> Result<(), flash::Error> {
+        let [opcode, result] = ack.encode();
+        let word = u32::from_le_bytes([MARKER, opcode, result, 0]);
+        self.flash.write_word(BASE_ADDR as *mut u32, word)
+    }
+
+    /// Remove the pending acknowledgement.
+    pub fn clear(&mut self) -> Result<(), flash::Error> {
+        self.flash.write_word(BASE_ADDR as *mut u32, 0)
+    }
+}
@@ -2,6 +2,10 @@ use embedded_hal::{
     blocking::delay::{DelayMs, DelayUs},
     digital::v2::{InputPin, OutputPin},
 };
+use gfroerli_common::{
+    config::{Config, Ds18b20Resolution, MAX_PROBES, MAX_PROBE_SERIAL},
+    measurement::StatusFlags,
+};
 use one_wire_bus::{Address, OneWire, OneWireError, OneWireResult};
 
 /// Family code of the DS18B20
@@ -23,30 +27,167 @@ mod commands {
     /// the 9th byte (byte 8 – CRC) is read. The master may issue a reset to terminate reading at
     /// any time if only part of the scratchpad data is needed.
     pub const READ_SCRATCHPAD: u8 = 0xBE;
+
+    /// Write scratchpad
+    ///
+    /// This command allows the master to write 3 bytes of data to the DS18B20's scratchpad. The
+    /// first data byte is written into the TH register (byte 2 of the scratchpad), the second byte
+    /// is written into the TL register (byte 3), and the third byte is written into the
+    /// configuration register (byte 4).
+    pub const WRITE_SCRATCHPAD: u8 = 0x4E;
+
+    /// Copy scratchpad
+    ///
+    /// This command copies the contents of the scratchpad TH, TL and configuration registers
+    /// (bytes 2, 3 and 4) to EEPROM.
+    pub const COPY_SCRATCHPAD: u8 = 0x48;
+}
+
+/// Time needed to copy the scratchpad to the EEPROM of the DS18B20 (in ms)
+const COPY_SCRATCHPAD_TIME_MS: u16 = 10;
+
+/// Interval between two conversion checks in [`Ds18b20::wait_for_conversion`] (in µs)
+const CONVERSION_POLL_INTERVAL_US: u16 = 10_000;
+
+/// Value of the temperature register after power-on (85 °C, in 1/16 °C)
+const POWER_ON_RESET_RAW: i16 = 0x0550;
+
+/// Lower end of the measurement range (-55 °C, in 1/16 °C)
+const MIN_RAW: i16 = -55 * 16;
+
+/// Upper end of the measurement range (+125 °C, in 1/16 °C)
+const MAX_RAW: i16 = 125 * 16;
+
+/// The result of reading the temperature of a DS18B20, classified by its
+/// plausibility.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Ds18b20Reading {
+    /// A plausible temperature (raw value in 1/16 °C)
+    Valid(i16),
+    /// The power-on reset value of the temperature register (85 °C). The
+    /// sensor browned out after the conversion was started.
+    PowerOnReset,
+    /// The temperature is outside of the measurement range, or the scratchpad
+    /// reads all zeros or all ones (e.g. because the bus is disconnected).
+    OutOfRange,
+    /// The CRC of the scratchpad does not match.
+    CrcFailed,
+}
+
+impl Ds18b20Reading {
+    /// Classify the contents of the scratchpad.
+    ///
+    /// A stuck bus is not necessarily detected by the CRC check (the CRC of
+    /// all zeros is zero), so the bus patterns are checked first.
+    fn from_scratchpad(scratchpad: &[u8; 9]) -> Self {
+        if scratchpad.iter().all(|&b| b == 0x00) || scratchpad.iter().all(|&b| b == 0xFF) {
+            return Self::OutOfRange;
+        }
+        if one_wire_bus::crc::check_crc8::<()>(scratchpad).is_err() {
+            return Self::CrcFailed;
+        }
+
+        // 12-bit raw temperature data is in bytes 0 and 1. The upper 5 bits
+        // of byte 1 contain the sign.
+        let raw = i16::from_le_bytes([scratchpad[0], scratchpad[1]]);
+        if raw == POWER_ON_RESET_RAW {
+            Self::PowerOnReset
+        } else if !(MIN_RAW..=MAX_RAW).contains(&raw) {
+            Self::OutOfRange
+        } else {
+            Self::Valid(Ds18b20Resolution::from_config_register(scratchpad[4]).mask_raw(raw))
+        }
+    }
+
+    /// Return the status flag reporting why the reading is not valid.
+    pub fn status_flag(&self) -> Option<StatusFlags> {
+        match self {
+            Self::Valid(_) => None,
+            Self::PowerOnReset => Some(StatusFlags::DS18B20_POWER_ON_RESET),
+            Self::OutOfRange => Some(StatusFlags::DS18B20_OUT_OF_RANGE),
+            Self::CrcFailed => Some(StatusFlags::DS18B20_CRC_ERROR),
+        }
+    }
 }
 
 #[derive(Copy, Clone)]
 pub struct Ds18b20(Address);
 
+/// A fixed-capacity list of the DS18B20 probes found on the one-wire bus.
+#[derive(Copy, Clone)]
+pub struct Ds18b20List {
+    probes: [Ds18b20; MAX_PROBES],
+    len: usize,
+}
+
+impl Ds18b20List {
+    /// Return the probes found, in the order of the bus search.
+    pub fn as_slice(&self) -> &[Ds18b20] {
+        &self.probes[..self.len]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Assign the probes to positions, according to the probe order in the
+    /// config (see [`Config::probe_positions`]).
+    pub fn ordered(&self, config: &Config) -> [Option<Ds18b20>; MAX_PROBES] {
+        let mut serials = [0; MAX_PROBES];
+        for (serial, probe) in serials.iter_mut().zip(self.as_slice()) {
+            *serial = probe.serial();
+        }
+        let positions = config.probe_positions(&serials[..self.len]);
+        let mut ordered = [None; MAX_PROBES];
+        for (probe, index) in ordered.iter_mut().zip(positions.iter()) {
+            *probe = index.map(|index| self.probes[index]);
+        }
+        ordered
+    }
+}
+
 impl Ds18b20 {
-    /// Scan the one-wire bus for a DS18B20 sensor. Return the first sensor found.
-    pub fn find<P, E>(
+    /// Scan the one-wire bus for DS18B20 sensors. Return up to `MAX_PROBES`
+    /// sensors, further sensors are ignored.
+    ///
+    /// If no sensor is found, an empty list is returned.
+    pub fn find_all<P, E>(
         one_wire_bus: &mut OneWire<P>,
         delay: &mut (impl DelayUs<u16> + DelayMs<u16>),
-    ) -> OneWireResult<Self, E>
+    ) -> OneWireResult<Ds18b20List, E>
     where
         P: OutputPin<Error = E> + InputPin<Error = E>,
     {
+        let mut list = Ds18b20List {
+            probes: [Self(Address(0)); MAX_PROBES],
+            len: 0,
+        };
         for device_address in one_wire_bus.devices(false, delay) {
             let addr = device_address?;
-            if addr.family_code() == FAMILY_CODE_DS18B20 {
-                return Ok(Self(addr));
+            if addr.family_code() != FAMILY_CODE_DS18B20 {
+                continue;
             }
+            if list.len == MAX_PROBES {
+                break;
+            }
+            list.probes[list.len] = Self(addr);
+            list.len += 1;
         }
-        Err(OneWireError::Timeout)
+        Ok(list)
     }
 
-    /// Start a temperature measurement.
+    /// Return the 64 bit ROM address (family code, serial number and CRC).
+    pub fn rom_address(&self) -> u64 {
+        self.0 .0
+    }
+
+    /// Return the 48 bit serial number, the part of the ROM address between
+    /// family code and CRC.
+    pub fn serial(&self) -> u64 {
+        (self.0 .0 >> 8) & MAX_PROBE_SERIAL
+    }
+
+    /// Start a temperature measurement on this sensor only.
     pub fn start_measurement<P, E>(
         &self,
         one_wire_bus: &mut OneWire<P>,
@@ -58,35 +199,221 @@ impl Ds18b20 {
         one_wire_bus.send_command(commands::CONVERT_TEMP, Some(&self.0), delay)
     }
 
-    /// Return the raw DS18B20 temperature data from the scratchpad register.
+    /// Start a temperature measurement on all DS18B20 sensors on the bus at
+    /// the same time.
     ///
-    /// NOTE: The resolution of the temperature sensor is user-configurable to 9, 10, 11, or
-    /// 12 bits, corresponding to increments of 0.5°C, 0.25°C, 0.125°C, and 0.0625°C, respectively.
-    /// The default resolution at power-up is 12-bit. Because we never set the resolution, we
-    /// can rely on the fact that it's always 12-bit (increments of 0.0625°C).
-    pub fn read_raw_temperature_data<P, E>(
+    /// The command is sent with "skip ROM", so all sensors start their
+    /// conversion simultaneously and the conversion time does not grow with
+    /// the number of sensors.
+    pub fn start_measurement_all<P, E>(
+        one_wire_bus: &mut OneWire<P>,
+        delay: &mut (impl DelayUs<u16> + DelayMs<u16>),
+    ) -> OneWireResult<(), E>
+    where
+        P: OutputPin<Error = E> + InputPin<Error = E>,
+    {
+        one_wire_bus.send_command(commands::CONVERT_TEMP, None, delay)
+    }
+
+    /// Return whether the temperature conversion has finished.
+    ///
+    /// After a `CONVERT_TEMP` command, the DS18B20 responds to read time slots with 0 while the
+    /// conversion is in progress and with 1 when it is done. If multiple sensors convert at the
+    /// same time (see [`start_measurement_all`](Self::start_measurement_all)), the bus reads 0
+    /// until all of them are done.
+    ///
+    /// NOTE: This only works if the sensors are powered externally. In parasite power mode, the
+    /// sensors cannot pull the bus low, so the conversion always seems to be done.
+    pub fn is_conversion_done<P, E>(
+        one_wire_bus: &mut OneWire<P>,
+        delay: &mut impl DelayUs<u16>,
+    ) -> OneWireResult<bool, E>
+    where
+        P: OutputPin<Error = E> + InputPin<Error = E>,
+    {
+        one_wire_bus.read_bit(delay)
+    }
+
+    /// Block until the temperature conversion has finished.
+    ///
+    /// Returns `OneWireError::Timeout` if the conversion has not finished within `timeout_ms`.
+    /// This blocks the caller, in RTIC tasks [`is_conversion_done`](Self::is_conversion_done)
+    /// should be polled from a scheduled task instead.
+    pub fn wait_for_conversion<P, E>(
+        one_wire_bus: &mut OneWire<P>,
+        timeout_ms: u32,
+        delay: &mut impl DelayUs<u16>,
+    ) -> OneWireResult<(), E>
+    where
+        P: OutputPin<Error = E> + InputPin<Error = E>,
+    {
+        let mut elapsed_ms = 0;
+        loop {
+            if Self::is_conversion_done(one_wire_bus, delay)? {
+                return Ok(());
+            }
+            if elapsed_ms >= timeout_ms {
+                return Err(OneWireError::Timeout);
+            }
+            delay.delay_us(CONVERSION_POLL_INTERVAL_US);
+            elapsed_ms += u32::from(CONVERSION_POLL_INTERVAL_US / 1000);
+        }
+    }
+
+    /// Read the scratchpad without verifying its CRC.
+    fn read_scratchpad_unchecked<P, E>(
         &self,
         one_wire_bus: &mut OneWire<P>,
         delay: &mut (impl DelayUs<u16> + DelayMs<u16>),
-    ) -> OneWireResult<u16, E>
+    ) -> OneWireResult<[u8; 9], E>
     where
         P: OutputPin<Error = E> + InputPin<Error = E>,
     {
         one_wire_bus.send_command(commands::READ_SCRATCHPAD, Some(&self.0), delay)?;
 
-        // We're only interested in the first two bytes, but we still want to read 9 bytes
-        // in order to be able to verify the CRC.
+        // Read all 9 bytes in order to be able to verify the CRC.
         let mut scratchpad = [0; 9];
         one_wire_bus.read_bytes(&mut scratchpad, delay)?;
+        Ok(scratchpad)
+    }
+
+    /// Read the scratchpad and verify its CRC.
+    fn read_scratchpad<P, E>(
+        &self,
+        one_wire_bus: &mut OneWire<P>,
+        delay: &mut (impl DelayUs<u16> + DelayMs<u16>),
+    ) -> OneWireResult<[u8; 9], E>
+    where
+        P: OutputPin<Error = E> + InputPin<Error = E>,
+    {
+        let scratchpad = self.read_scratchpad_unchecked(one_wire_bus, delay)?;
         one_wire_bus::crc::check_crc8(&scratchpad)?;
+        Ok(scratchpad)
+    }
+
+    /// Set the conversion resolution.
+    ///
+    /// The configuration is only written (and copied to the EEPROM of the
+    /// DS18B20, so that it survives a power cycle) if the current resolution
+    /// differs. This avoids wearing out the EEPROM, since this is called on
+    /// every wakeup. The alarm registers TH and TL are preserved.
+    pub fn set_resolution<P, E>(
+        &self,
+        resolution: Ds18b20Resolution,
+        one_wire_bus: &mut OneWire<P>,
+        delay: &mut (impl DelayUs<u16> + DelayMs<u16>),
+    ) -> OneWireResult<(), E>
+    where
+        P: OutputPin<Error = E> + InputPin<Error = E>,
+    {
+        let scratchpad = self.read_scratchpad(one_wire_bus, delay)?;
+        if Ds18b20Resolution::from_config_register(scratchpad[4]) == resolution {
+            return Ok(());
+        }
 
-        // 12-bit raw temperature data is in bytes 0 and 1
-        if cfg!(feature = "dev") {
-            assert!(
-                (scratchpad[1] & 0xf0) == 0,
-                "Raw data contains more than 12 data bits"
+        one_wire_bus.send_command(commands::WRITE_SCRATCHPAD, Some(&self.0), delay)?;
+        one_wire_bus.write_bytes(
+            &[scratchpad[2], scratchpad[3], resolution.config_register()],
+            delay,
+        )?;
+        one_wire_bus.send_command(commands::COPY_SCRATCHPAD, Some(&self.0), delay)?;
+        delay.delay_ms(COPY_SCRATCHPAD_TIME_MS);
+        Ok(())
+    }
+
+    /// Read the temperature from the scratchpad register and classify it.
+    ///
+    /// Valid readings contain the raw temperature data in two's complement
+    /// format (in 1/16 °C), negative temperatures result in negative values.
+    ///
+    /// NOTE: The resolution of the temperature sensor is user-configurable to 9, 10, 11, or
+    /// 12 bits, corresponding to increments of 0.5°C, 0.25°C, 0.125°C, and 0.0625°C, respectively.
+    /// At a lower resolution, the least significant bits of the temperature register are
+    /// undefined. They are cleared according to the resolution in the configuration register.
+    ///
+    /// Only bus errors are returned as `Err`, implausible data is reported as
+    /// a [`Ds18b20Reading`] variant.
+    pub fn read_temperature<P, E>(
+        &self,
+        one_wire_bus: &mut OneWire<P>,
+        delay: &mut (impl DelayUs<u16> + DelayMs<u16>),
+    ) -> OneWireResult<Ds18b20Reading, E>
+    where
+        P: OutputPin<Error = E> + InputPin<Error = E>,
+    {
+        let scratchpad = self.read_scratchpad_unchecked(one_wire_bus, delay)?;
+        Ok(Ds18b20Reading::from_scratchpad(&scratchpad))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a scratchpad with the given temperature, 12 bit resolution and
+    /// a valid CRC.
+    fn scratchpad(raw: i16) -> [u8; 9] {
+        let [lsb, msb] = raw.to_le_bytes();
+        let mut scratchpad = [lsb, msb, 0x4B, 0x46, 0x7F, 0xFF, 0x0C, 0x10, 0];
+        scratchpad[8] = one_wire_bus::crc::calculate_crc8(&scratchpad[..8]);
+        scratchpad
+    }
+
+    #[test]
+    fn test_reading_valid() {
+        for raw in [0x0191, 0, -1, -0x0370, 0x07D0] {
+            assert_eq!(
+                Ds18b20Reading::from_scratchpad(&scratchpad(raw)),
+                Ds18b20Reading::Valid(raw)
+            );
+        }
+    }
+
+    #[test]
+    fn test_reading_masks_resolution() {
+        let mut data = scratchpad(0x0197);
+        // 9 bit resolution
+        data[4] = 0x1F;
+        data[8] = one_wire_bus::crc::calculate_crc8(&data[..8]);
+        assert_eq!(
+            Ds18b20Reading::from_scratchpad(&data),
+            Ds18b20Reading::Valid(0x0190)
+        );
+    }
+
+    #[test]
+    fn test_reading_power_on_reset() {
+        let reading = Ds18b20Reading::from_scratchpad(&scratchpad(0x0550));
+        assert_eq!(reading, Ds18b20Reading::PowerOnReset);
+        assert_eq!(
+            reading.status_flag(),
+            Some(StatusFlags::DS18B20_POWER_ON_RESET)
+        );
+    }
+
+    #[test]
+    fn test_reading_out_of_range() {
+        for data in [
+            [0x00; 9],
+            [0xFF; 9],
+            scratchpad(0x07D1),
+            scratchpad(-0x0371),
+        ] {
+            let reading = Ds18b20Reading::from_scratchpad(&data);
+            assert_eq!(reading, Ds18b20Reading::OutOfRange);
+            assert_eq!(
+                reading.status_flag(),
+                Some(StatusFlags::DS18B20_OUT_OF_RANGE)
             );
         }
-        Ok(u16::from_le_bytes([scratchpad[0], scratchpad[1]]))
+    }
+
+    #[test]
+    fn test_reading_crc_failed() {
+        let mut data = scratchpad(0x0191);
+        data[8] ^= 0x01;
+        let reading = Ds18b20Reading::from_scratchpad(&data);
+        assert_eq!(reading, Ds18b20Reading::CrcFailed);
+        assert_eq!(reading.status_flag(), Some(StatusFlags::DS18B20_CRC_ERROR));
     }
 }
@@ -0,0 +1,60 @@
+//! Data EEPROM writer.
+//!
+//! Adapts the data EEPROM to the [`Eeprom`] trait used by the config slots.
+//! Words are written through the HAL, like in the other EEPROM stores.
+
+use gfroerli_common::{
+    config_slots::Eeprom,
+    memory_map::{EEPROM_END, EEPROM_START},
+};
+use stm32l0xx_hal::flash::{self, FLASH};
+
+/// Errors that can occur when writing to the data EEPROM.
+#[derive(Debug)]
+pub enum Error {
+    /// The address is not word aligned or outside of the data EEPROM
+    InvalidAddress,
+    /// Writing the word failed
+    Flash(flash::Error),
+}
+
+impl From<flash::Error> for Error {
+    fn from(error: flash::Error) -> Self {
+        Self::Flash(error)
+    }
+}
+
+/// Writer for a region of the data EEPROM.
+pub struct DataEeprom<'a> {
+    flash: &'a mut FLASH,
+    base_addr: usize,
+}
+
+impl<'a> DataEeprom<'a> {
+    /// Create a writer for the EEPROM region starting at `base_addr`. Offsets
+    /// passed to the [`Eeprom`] methods are relative to this address.
+    pub fn new(flash: &'a mut FLASH, base_addr: usize) -> Self {
+        Self { flash, base_addr }
+    }
+}
+
+impl<'a> Eeprom for DataEeprom<'a> {
+    type Error = Error;
+
+    fn read(&self, offset: usize, buf: &mut [u8]) {
+        // Note(unsafe): Read with no side effects. This is safe since we hold
+        // a mutable reference to the FLASH peripheral.
+        let data: &[u8] = unsafe {
+            core::slice::from_raw_parts((self.base_addr + offset) as *const u8, buf.len())
+        };
+        buf.copy_from_slice(data);
+    }
+
+    fn write_word(&mut self, offset: usize, word: u32) -> Result<(), Error> {
+        let addr = self.base_addr + offset;
+        if addr % 4 != 0 || addr < EEPROM_START || addr + 4 > EEPROM_END {
+            return Err(Error::InvalidAddress);
+        }
+        Ok(self.flash.write_word(addr as *mut u32, word)?)
+    }
+}
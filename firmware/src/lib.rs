@@ -1,4 +1,5 @@
 #![cfg_attr(not(test), no_std)]
 pub mod delay;
+pub mod ds18b20;
 pub mod rtc;
 pub mod supply_monitor;
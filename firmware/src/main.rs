@@ -2,17 +2,54 @@
 #![no_std]
 #![cfg(target_arch = "arm")]
 
+use core::fmt::Write;
+
+use embedded_hal::blocking::delay::DelayMs;
+use gfroerli_common::{
+    command::{Command, CommandAck, CommandResult, FPORT_COMMAND, MAX_COMMAND_LEN},
+    config::{self, Config, ConfigVersion},
+    config_slots,
+    measurement::StatusFlags,
+    panic::{FPORT_PANIC, MAX_PANIC_MSG_LEN},
+};
+use rn2xx3::{ConfirmationMode, DataRateEuCn, JoinMode};
+use stm32l0xx_hal::{flash::FLASH, pac, serial::Serial};
+
+use crate::{ack_store::AckStore, eeprom::DataEeprom, panic_store::PanicStore};
+
 // Modules
+mod ack_store;
 mod delay;
-mod ds18b20;
+mod eeprom;
 mod leds;
 mod monotonic_stm32l0;
+mod panic_store;
 mod rtc;
+mod sample_buffer;
 mod supply_monitor;
 mod version;
 
+/// Type alias for the RN2483 driver
+type Rn2483 = rn2xx3::Driver<rn2xx3::Freq868, Serial<pac::LPUART1>>;
+
 const FIRMWARE_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// Supply voltage (in mV) below which the low battery status flag is set.
+const LOW_BATTERY_THRESHOLD_MV: u16 = 3300;
+
+/// Number of OTAA join attempts before giving up until the next wakeup.
+const OTAA_JOIN_ATTEMPTS: u8 = 5;
+
+/// Back-off (in seconds) after the first failed OTAA join attempt. Doubled
+/// after every further failed attempt.
+const OTAA_JOIN_BACKOFF_SECONDS: u16 = 5;
+
+/// Maximum duration (in ms) of an SHTC3 measurement in normal mode.
+const SHTC3_MEASUREMENT_TIME_MS: u32 = 13;
+
+/// Interval (in ms) between two checks whether the DS18B20 conversion is done.
+const DS18B20_POLL_INTERVAL_MS: u32 = 10;
+
 /// Helper to convert a boolean to a static emoji. Used when logging.
 fn bool_to_emoji(val: bool) -> &'static str {
     if val {
@@ -22,6 +59,318 @@ fn bool_to_emoji(val: bool) -> &'static str {
     }
 }
 
+/// Transmit an uplink and log the result.
+///
+/// A downlink command received in reply is applied to the config (see
+/// [`handle_command`]).
+///
+/// Returns whether the transmission succeeded.
+fn transmit(
+    rn: &mut Rn2483,
+    debug: &mut Serial<pac::USART1>,
+    flash: &mut FLASH,
+    config: &mut Config,
+    mode: ConfirmationMode,
+    fport: u8,
+    data: &[u8],
+) -> bool {
+    match rn.transmit_slice(mode, fport, data) {
+        Ok(None) => {
+            writeln!(debug, "Uplink succeeded, no downlink").unwrap();
+            true
+        }
+        Ok(Some(downlink)) => {
+            writeln!(debug, "Downlink: {:?}", downlink).unwrap();
+            if downlink.port() == FPORT_COMMAND {
+                handle_command(rn, debug, flash, config, downlink.hexdata());
+            }
+            true
+        }
+        Err(e) => {
+            writeln!(debug, "Error: Transmitting LoRaWAN package failed: {:?}", e).unwrap();
+            false
+        }
+    }
+}
+
+/// Decode a hex string into the buffer, return the number of decoded bytes.
+///
+/// Input that does not fit into the buffer is ignored. Returns `None` if the
+/// input is not valid hex.
+fn decode_hex(hex: &str, buf: &mut [u8]) -> Option<usize> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    let mut length = 0;
+    for (byte, chunk) in buf.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        let chunk = core::str::from_utf8(chunk).ok()?;
+        *byte = u8::from_str_radix(chunk, 16).ok()?;
+        length += 1;
+    }
+    Some(length)
+}
+
+/// Decode a hex encoded downlink command and apply it to the config.
+///
+/// The updated config is persisted to EEPROM and only takes effect once it
+/// has been persisted. Otherwise the device keeps running the old config,
+/// which is also the one it loads after a reset. The result is stored as
+/// pending acknowledgement, which is transmitted with the next uplink (see
+/// [`transmit_pending_ack`]).
+fn handle_command(
+    rn: &mut Rn2483,
+    debug: &mut Serial<pac::USART1>,
+    flash: &mut FLASH,
+    config: &mut Config,
+    hexdata: &str,
+) {
+    // Decode hex data. Downlinks that are longer than any command are
+    // truncated, they are rejected by the command decoder anyway.
+    let mut buf = [0u8; MAX_COMMAND_LEN + 1];
+    let data = match decode_hex(hexdata, &mut buf) {
+        Some(length) => &buf[..length],
+        None => {
+            writeln!(debug, "Invalid downlink hex data: {}", hexdata).unwrap();
+            &[]
+        }
+    };
+
+    let opcode = data.first().copied().unwrap_or(0);
+    let result = match Command::decode(data) {
+        Ok(command) => {
+            writeln!(debug, "Command: {:?}", command).unwrap();
+            match command.apply(config.clone()) {
+                Ok(mut updated) => {
+                    let mut eeprom = DataEeprom::new(flash, config::BASE_ADDR);
+                    match config_slots::store(&mut eeprom, &updated) {
+                        Ok(slot) => {
+                            writeln!(
+                                debug,
+                                "Persisted config to slot {} (generation {})",
+                                slot.slot, slot.generation
+                            )
+                            .unwrap();
+                            // The config is always stored in the current
+                            // format version
+                            updated.version = ConfigVersion::V2;
+                            *config = updated;
+                            // Apply the new data rate immediately, it is
+                            // persisted by the next "mac save"
+                            if let Command::SetDataRate(index) = command {
+                                rn.set_data_rate(data_rate(index)).unwrap_or_else(|e| {
+                                    writeln!(debug, "RN2483: Could not set data rate: {:?}", e)
+                                        .unwrap()
+                                });
+                            }
+                            CommandResult::Applied
+                        }
+                        Err(e) => {
+                            writeln!(debug, "Could not persist config: {:?}", e).unwrap();
+                            CommandResult::StorageError
+                        }
+                    }
+                }
+                Err(e) => {
+                    writeln!(debug, "Command rejected: {}", e).unwrap();
+                    CommandResult::Invalid
+                }
+            }
+        }
+        Err(e) => {
+            writeln!(debug, "Invalid command: {}", e).unwrap();
+            CommandResult::Malformed
+        }
+    };
+    AckStore::new(flash)
+        .store(&CommandAck { opcode, result })
+        .unwrap_or_else(|e| {
+            writeln!(debug, "Could not store command acknowledgement: {:?}", e).unwrap()
+        });
+}
+
+/// Transmit the pending command acknowledgement, if any, and clear it once it
+/// has been sent.
+fn transmit_pending_ack(
+    rn: &mut Rn2483,
+    debug: &mut Serial<pac::USART1>,
+    flash: &mut FLASH,
+    config: &mut Config,
+) {
+    if let Some(ack) = AckStore::new(flash).load() {
+        writeln!(
+            debug,
+            "📣 Transmitting command acknowledgement (opcode=0x{:02x} result={})...",
+            ack.opcode, ack.result
+        )
+        .unwrap();
+
+        // Clear the acknowledgement before transmitting, since the reply may
+        // contain a new command that stores a new acknowledgement.
+        AckStore::new(flash).clear().unwrap_or_else(|e| {
+            writeln!(debug, "Could not clear command acknowledgement: {:?}", e).unwrap()
+        });
+        if !transmit(
+            rn,
+            debug,
+            flash,
+            config,
+            ConfirmationMode::Unconfirmed,
+            FPORT_COMMAND,
+            &ack.encode(),
+        ) {
+            // Retry with the next uplink
+            AckStore::new(flash).store(&ack).unwrap_or_else(|e| {
+                writeln!(debug, "Could not store command acknowledgement: {:?}", e).unwrap()
+            });
+        }
+    }
+}
+
+/// Return the confirmation mode for the next measurement uplink.
+///
+/// Every n-th uplink (based on the RN2483 uplink counter, which survives
+/// standby) is confirmed. If `confirmed_every_n` is 0, all uplinks are
+/// unconfirmed.
+fn measurement_confirmation_mode(rn: &mut Rn2483, confirmed_every_n: u8) -> ConfirmationMode {
+    if confirmed_every_n == 0 {
+        return ConfirmationMode::Unconfirmed;
+    }
+    match rn.get_upctr() {
+        Ok(upctr) if upctr % u32::from(confirmed_every_n) == 0 => ConfirmationMode::Confirmed,
+        _ => ConfirmationMode::Unconfirmed,
+    }
+}
+
+/// Map a LoRaWAN data rate index (as stored in the config) to the RN2483
+/// data rate.
+fn data_rate(index: u8) -> DataRateEuCn {
+    match index {
+        0 => DataRateEuCn::Sf12Bw125,
+        1 => DataRateEuCn::Sf11Bw125,
+        2 => DataRateEuCn::Sf10Bw125,
+        3 => DataRateEuCn::Sf9Bw125,
+        4 => DataRateEuCn::Sf8Bw125,
+        _ => DataRateEuCn::Sf7Bw125,
+    }
+}
+
+/// Return the data rate index of the next uplink. With ADR enabled, the
+/// network server may have changed it since it was configured.
+///
+/// Falls back to data rate 0, which allows the shortest payload, if the
+/// data rate cannot be read.
+fn current_data_rate(rn: &mut Rn2483) -> u8 {
+    rn.send_raw_command_str(&["mac get dr"])
+        .ok()
+        .and_then(|dr| dr.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Apply the radio configuration (data rate, transmit power and ADR) to the
+/// RN2483.
+fn apply_radio_config(rn: &mut Rn2483, debug: &mut Serial<pac::USART1>, config: &Config) {
+    writeln!(
+        debug,
+        "RN2483: Setting data rate {}, power index {}, ADR {}",
+        config.data_rate,
+        config.tx_power_index,
+        bool_to_emoji(config.adr)
+    )
+    .unwrap();
+    rn.set_data_rate(data_rate(config.data_rate))
+        .expect("Could not set data rate");
+
+    // The power index is validated to be a single digit
+    let pwridx = [b'0' + config.tx_power_index];
+    let pwridx = core::str::from_utf8(&pwridx).unwrap();
+    rn.send_raw_command_ok(&["mac set pwridx ", pwridx])
+        .expect("Could not set power index");
+    let adr = if config.adr { "on" } else { "off" };
+    rn.send_raw_command_ok(&["mac set adr ", adr])
+        .expect("Could not set ADR");
+}
+
+/// Join the LoRaWAN network via OTAA, retrying with exponential back-off.
+///
+/// The OTAA credentials must already be configured in the RN2483. Returns
+/// whether the join succeeded.
+fn join_otaa(
+    rn: &mut Rn2483,
+    debug: &mut Serial<pac::USART1>,
+    delay: &mut impl DelayMs<u16>,
+) -> bool {
+    let mut backoff_seconds = OTAA_JOIN_BACKOFF_SECONDS;
+    for attempt in 1..=OTAA_JOIN_ATTEMPTS {
+        writeln!(
+            debug,
+            "RN2483: OTAA join attempt {}/{}",
+            attempt, OTAA_JOIN_ATTEMPTS
+        )
+        .unwrap();
+        match rn.join(JoinMode::Otaa) {
+            Ok(()) => return true,
+            Err(e) => writeln!(debug, "RN2483: OTAA join failed: {:?}", e).unwrap(),
+        }
+        if attempt < OTAA_JOIN_ATTEMPTS {
+            writeln!(debug, "  Retrying in {} s", backoff_seconds).unwrap();
+            for _ in 0..backoff_seconds {
+                delay.delay_ms(1000);
+            }
+            backoff_seconds = backoff_seconds.saturating_mul(2);
+        }
+    }
+    false
+}
+
+/// Transmit the stored panic record, if any, and clear it once it has been
+/// sent.
+fn transmit_pending_panic(
+    rn: &mut Rn2483,
+    debug: &mut Serial<pac::USART1>,
+    flash: &mut FLASH,
+    config: &mut Config,
+) {
+    let record = PanicStore::new(flash).load();
+    if let Some(record) = record {
+        let mut buf = [0u8; MAX_PANIC_MSG_LEN];
+        let length = record.encode(&mut buf);
+        writeln!(
+            debug,
+            "📣 Transmitting panic record (hash={:08x} location={})...",
+            record.hash,
+            record.location()
+        )
+        .unwrap();
+        if transmit(
+            rn,
+            debug,
+            flash,
+            config,
+            ConfirmationMode::Unconfirmed,
+            FPORT_PANIC,
+            &buf[..length],
+        ) {
+            PanicStore::new(flash).clear().unwrap_or_else(|e| {
+                writeln!(debug, "Could not clear panic record: {:?}", e).unwrap()
+            });
+        }
+    }
+}
+
+/// Return the time after which a DS18B20 conversion is considered to have
+/// failed: twice the maximum conversion time of the configured resolution.
+fn ds18b20_conversion_timeout_ms(config: &Config) -> u32 {
+    config.water_temp_resolution.conversion_time_ms() * 2
+}
+
+/// Map an SHTCx error to the corresponding status flag.
+fn sht_error_flag<E>(error: &shtcx::Error<E>) -> StatusFlags {
+    match error {
+        shtcx::Error::Crc => StatusFlags::SHTC3_CRC_ERROR,
+        shtcx::Error::I2c(_) => StatusFlags::SHTC3_I2C_ERROR,
+    }
+}
+
 #[rtic::app(
     device = stm32l0xx_hal::pac,
     peripherals = true,
@@ -35,7 +384,7 @@ mod app {
     use embedded_time::rate::{Baud, Extensions};
     use one_wire_bus::OneWire;
     use panic_persist as _;
-    use rn2xx3::{rn2483_868, ConfirmationMode, DataRateEuCn, Driver as Rn2xx3, Freq868, JoinMode};
+    use rn2xx3::{rn2483_868, ConfirmationMode, JoinMode};
     use shtcx::{shtc3, Error as ShtError, LowPower, PowerMode, ShtC3};
     use stm32l0xx_hal::gpio::{
         gpioa::{PA10, PA6, PA9},
@@ -43,6 +392,7 @@ mod app {
     };
     use stm32l0xx_hal::{
         self as hal,
+        flash::FLASH,
         i2c::I2c,
         pac,
         prelude::*,
@@ -53,19 +403,35 @@ mod app {
 
     // First party crates
     use gfroerli_common::{
-        config::{self, Config},
-        measurement::{EncodedMeasurement, MeasurementMessage, MAX_MSG_LEN, U12},
+        config::{self, Activation, Config, ConfigVersion, Sensor, MAX_PROBES},
+        config_slots,
+        measurement::{
+            max_payload_len, BatchMessage, BatchRecord, DecodedMeasurement, EncodedMeasurement,
+            MeasurementMessage, Millivolts, StatusFlags, FPORT_BATCH, FPORT_MEASUREMENT, I12,
+            MAX_BATCH_MSG_LEN, MAX_BATCH_SIZE, MAX_MSG_LEN,
+        },
+        panic::PanicRecord,
+        status::{self, StatusMessage, Version, FPORT_STATUS},
     };
+    use gfroerli_firmware::ds18b20::{Ds18b20, Ds18b20Reading};
 
     // Crate-internal
     use crate::{
-        bool_to_emoji,
+        apply_radio_config, bool_to_emoji, current_data_rate,
         delay::Tim7Delay,
-        ds18b20::Ds18b20,
+        ds18b20_conversion_timeout_ms,
+        eeprom::DataEeprom,
+        join_otaa,
         leds::StatusLeds,
+        measurement_confirmation_mode,
         monotonic_stm32l0::{ExtU32, ExtendedLptim},
+        panic_store::PanicStore,
+        sample_buffer::SampleBuffer,
+        sht_error_flag,
         supply_monitor::SupplyMonitor,
+        transmit, transmit_pending_ack, transmit_pending_panic,
         version::HardwareVersionDetector,
+        Rn2483,
     };
 
     /// Type alias for I2C1
@@ -80,6 +446,16 @@ mod app {
     }
 
     impl MeasurementPlan {
+        /// Determine which sensors should be measured in the given wakeup
+        /// cycle, based on the enabled sensors and their intervals.
+        fn new(config: &Config, wakeup_cycle: u32) -> Self {
+            Self {
+                measure_sht: config.should_measure(Sensor::TempHumi, wakeup_cycle),
+                measure_ds18b20: config.should_measure(Sensor::WaterTemp, wakeup_cycle),
+                measure_voltage: config.should_measure(Sensor::Voltage, wakeup_cycle),
+            }
+        }
+
         fn should_transmit(self) -> bool {
             self.measure_sht || self.measure_ds18b20 || self.measure_voltage
         }
@@ -106,11 +482,11 @@ mod app {
         #[lock_free]
         sht: ShtC3<I2C1>,
 
-        // DS18B20 water temperature sensor
+        // DS18B20 water temperature sensors, in probe order
         #[lock_free]
         one_wire: OneWire<PA6<Output<OpenDrain>>>,
         #[lock_free]
-        ds18b20: Option<Ds18b20>,
+        ds18b20: [Option<Ds18b20>; MAX_PROBES],
 
         // Blocking delay provider
         #[lock_free]
@@ -122,11 +498,17 @@ mod app {
         // Base measurement plan, based purely on wakeup cycle and config
         base_measurement_plan: MeasurementPlan,
 
+        // Base status flags, collected during initialization
+        base_status: StatusFlags,
+
         // Supply voltage monitor
         supply_monitor: SupplyMonitor,
 
         // RN2483
-        rn: Rn2xx3<Freq868, hal::serial::Serial<pac::LPUART1>>,
+        rn: Rn2483,
+
+        // FLASH peripheral, used for writing to EEPROM
+        flash: FLASH,
 
         // Power peripheral, RTC and SCB register, used for putting the device
         // into standby mode
@@ -161,6 +543,19 @@ mod app {
         let pwr = pwr::PWR::new(dp.PWR, &mut rcc);
         let scb = cp.SCB;
 
+        // Check whether we woke up from standby mode (as opposed to a cold
+        // boot after power-up or a reset), then clear the standby flag.
+        //
+        // Note(unsafe): The PWR peripheral is owned by the HAL wrapper, which
+        // does not expose the standby flag. We only read the CSR register and
+        // clear the flag, which does not interfere with the HAL.
+        let woke_from_standby = {
+            let pwr_regs = unsafe { &*pac::PWR::ptr() };
+            let standby_flag = pwr_regs.csr.read().sbf().bit_is_set();
+            pwr_regs.cr.modify(|_, w| w.csbf().set_bit());
+            standby_flag
+        };
+
         // Instantiate RTC peripheral
         let mut rtc = Rtc::new(dp.RTC, &mut rcc, &pwr, None).unwrap(); // Cannot fail, since no `init` value is passed in
 
@@ -213,8 +608,15 @@ mod app {
         )
         .unwrap();
 
+        // Status flags, reported along with the measurements
+        let mut status = StatusFlags::default();
+
         // Check whether we just woke up after a panic
+        let mut panic_record = None;
         if let Some(msg) = panic_persist::get_panic_message_utf8() {
+            status.insert(StatusFlags::PANICKED);
+            panic_record = Some(PanicRecord::from_message(msg));
+
             // If yes, send backtrace via serial
             writeln!(debug, "=== 🔥 FOUND PANIC 🔥 ===").ok();
             writeln!(debug, "{}", msg.trim_end()).ok();
@@ -242,7 +644,7 @@ mod app {
             let config_data: &[u8] = unsafe {
                 core::slice::from_raw_parts(
                     config::BASE_ADDR as *const u8,
-                    config::CONFIG_DATA_SIZE,
+                    config_slots::CONFIG_SLOTS_SIZE,
                 )
             };
             for (i, byte) in config_data.iter().enumerate() {
@@ -254,27 +656,25 @@ mod app {
             write!(debug, "\n\n").unwrap();
         }
 
-        // Read config from EEPROM
-        let config = match {
-            // Note: We need to guarantee that no part of the code can write to
-            // EEPROM while it's being read. To ensure that, we hold a mutable
-            // reference to the FLASH peripheral.
-            let _flash = &mut dp.FLASH;
+        // Initialize FLASH peripheral (used for reading and writing EEPROM)
+        let mut flash = FLASH::new(dp.FLASH, &mut rcc);
 
-            // Note(unsafe): Read with no side effects. This is fine as long as
-            // the data in EEPROM is not being written while it's being read.
-            // This is safe as long as we hold a mutable reference to the flash
-            // peripheral. See comment above for details.
-            let config_data: &[u8] = unsafe {
-                core::slice::from_raw_parts(
-                    config::BASE_ADDR as *const u8,
-                    config::CONFIG_DATA_SIZE,
+        // Read config from the newest valid EEPROM slot.
+        //
+        // Note: The EEPROM writer holds a mutable reference to the FLASH
+        // peripheral, which guarantees that no part of the code can write to
+        // EEPROM while it's being read. Loading also validates the config
+        // values, e.g. that no interval is 0.
+        let mut config = match config_slots::load(&DataEeprom::new(&mut flash, config::BASE_ADDR)) {
+            Ok((c, slot)) => {
+                writeln!(
+                    debug,
+                    "🔧 Using config slot {} (generation {})",
+                    slot.slot, slot.generation
                 )
-            };
-
-            Config::from_slice(config_data)
-        } {
-            Ok(c) => c,
+                .unwrap();
+                c
+            }
             Err(e) => {
                 writeln!(
                     debug,
@@ -290,10 +690,31 @@ mod app {
             }
         };
         writeln!(debug, "🔧 Loaded config (v{}) from EEPROM", config.version).unwrap();
+        if config.version == ConfigVersion::V1 {
+            writeln!(
+                debug,
+                "Warning: Config v1 has no checksum, please re-flash the config"
+            )
+            .unwrap();
+        }
         if cfg!(feature = "dev") {
             writeln!(debug, "Config: {:?}", config).unwrap();
         }
 
+        // Persist the panic record, so that it can be transmitted later. If
+        // an older record has not been sent yet, keep that one, since it
+        // usually describes the root cause.
+        if let Some(record) = panic_record {
+            let mut panic_store = PanicStore::new(&mut flash);
+            if panic_store.load().is_none() {
+                panic_store.store(&record).unwrap_or_else(|e| {
+                    writeln!(debug, "Could not store panic record: {:?}", e).unwrap()
+                });
+            } else {
+                writeln!(debug, "Keeping previous panic record, it was not sent yet").unwrap();
+            }
+        }
+
         // Measure current time to determine the wakeup cycle
         let now = rtc.now();
         writeln!(
@@ -322,17 +743,22 @@ mod app {
         writeln!(debug).unwrap();
 
         // Determine measurement plan
-        let measure_temp_humi = wakeup_cycle % (config.nth_temp_humi as u32) == 0;
-        let measure_voltage = wakeup_cycle % (config.nth_voltage as u32) == 0;
-        let measurement_plan = MeasurementPlan {
-            measure_sht: measure_temp_humi,
-            measure_ds18b20: measure_temp_humi,
-            measure_voltage,
-        };
+        let measurement_plan = MeasurementPlan::new(&config, wakeup_cycle);
+        writeln!(debug, "Config:").unwrap();
+        for sensor in Sensor::ALL {
+            writeln!(
+                debug,
+                "  {} = {} {}",
+                sensor.interval_name(),
+                config.nth(sensor),
+                bool_to_emoji(config.is_enabled(sensor)),
+            )
+            .unwrap();
+        }
         writeln!(
             debug,
-            "Config:\n  nth_temp_humi = {}\n  nth_voltage = {}",
-            config.nth_temp_humi, config.nth_voltage,
+            "  water_temp_resolution = {} bits",
+            config.water_temp_resolution as u8,
         )
         .unwrap();
         writeln!(
@@ -354,9 +780,42 @@ mod app {
         writeln!(debug, "Init DS18B20…").unwrap();
         let one_wire_pin = gpioa.pa6.into_open_drain_output();
         let mut one_wire = OneWire::new(one_wire_pin).unwrap();
-        let ds18b20 = Ds18b20::find(&mut one_wire, &mut delay)
-            .map_err(|err| writeln!(debug, "Could not find DS18B20: {:?}", err).unwrap())
-            .ok();
+        let ds18b20 = if config.enable_water_temp {
+            match Ds18b20::find_all(&mut one_wire, &mut delay) {
+                Ok(probes) if !probes.is_empty() => {
+                    let ordered = probes.ordered(&config);
+                    for (i, probe) in ordered.iter().enumerate() {
+                        if let Some(probe) = probe {
+                            writeln!(debug, "DS18B20 #{}: {:012X}", i + 1, probe.serial()).unwrap();
+                            probe
+                                .set_resolution(
+                                    config.water_temp_resolution,
+                                    &mut one_wire,
+                                    &mut delay,
+                                )
+                                .unwrap_or_else(|err| {
+                                    writeln!(debug, "Could not set DS18B20 resolution: {:?}", err)
+                                        .unwrap();
+                                    status.insert(StatusFlags::DS18B20_ERROR);
+                                });
+                        }
+                    }
+                    ordered
+                }
+                result => {
+                    if let Err(err) = result {
+                        writeln!(debug, "Could not find DS18B20: {:?}", err).unwrap();
+                    } else {
+                        writeln!(debug, "Could not find DS18B20").unwrap();
+                    }
+                    status.insert(StatusFlags::DS18B20_NOT_FOUND);
+                    [None; MAX_PROBES]
+                }
+            }
+        } else {
+            writeln!(debug, "DS18B20 is disabled").unwrap();
+            [None; MAX_PROBES]
+        };
 
         // Initialize LEDs
         writeln!(debug, "Initialize LEDs").unwrap();
@@ -378,9 +837,11 @@ mod app {
         // TODO: If no SHT measurement is scheduled, we could avoid initializing the SHT.
         writeln!(debug, "Init SHTC3…").unwrap();
         let mut sht = shtc3(i2c);
-        sht.wakeup(&mut delay).unwrap_or_else(|e| {
-            writeln!(debug, "SHTCx: Could not wake up sensor: {:?}", e).unwrap()
-        });
+        if config.enable_temp_humi {
+            sht.wakeup(&mut delay).unwrap_or_else(|e| {
+                writeln!(debug, "SHTCx: Could not wake up sensor: {:?}", e).unwrap()
+            });
+        }
 
         // Show device info
         writeln!(debug, "RN2483: Device info").unwrap();
@@ -388,9 +849,16 @@ mod app {
             Ok(hweui) => writeln!(debug, "  Hardware EUI: {}", hweui).unwrap(),
             Err(e) => writeln!(debug, "  Could not read hweui: {:?}", e).unwrap(),
         };
-        match rn.version() {
-            Ok(version) => writeln!(debug, "  Version: {}", version).unwrap(),
-            Err(e) => writeln!(debug, "  Could not read version: {:?}", e).unwrap(),
+        let rn_version = match rn.version() {
+            Ok(version) => {
+                writeln!(debug, "  Version: {}", version).unwrap();
+                // Version string looks like "RN2483 1.0.5 Oct 31 2018 15:06:52"
+                version.split_whitespace().nth(1).and_then(Version::parse)
+            }
+            Err(e) => {
+                writeln!(debug, "  Could not read version: {:?}", e).unwrap();
+                None
+            }
         };
         match rn.vdd() {
             Ok(vdd) => writeln!(debug, "  VDD voltage: {} mV", vdd).unwrap(),
@@ -399,21 +867,6 @@ mod app {
 
         // Print device address
         writeln!(debug, "RN2483: Setting keys...").unwrap();
-        writeln!(
-            debug,
-            "  Configured dev addr: {:x}{:x}{:x}{:x}",
-            config.devaddr[0], config.devaddr[1], config.devaddr[2], config.devaddr[3],
-        )
-        .unwrap();
-
-        // Check whether credentials have already been configured. Do this by
-        // looking at the device address. If it's still the default value
-        // (0x00000000), or if it's configured with a different device address,
-        // then set new keys. Otherwise, we can reuse the existing keys.
-        //
-        // (Note: This assumes that the keys for a device with a specific
-        // address do not change. If you want to change the keys, you must also
-        // change the device address.)
         let dev_addr = rn.get_dev_addr_slice().unwrap();
         writeln!(
             debug,
@@ -423,39 +876,139 @@ mod app {
         .unwrap();
         let upctr = rn.get_upctr().unwrap();
         writeln!(debug, "  Current up counter: {}", upctr).unwrap();
-        if dev_addr == config.devaddr {
-            writeln!(debug, "  Re-using previously stored credentials").unwrap();
-        } else {
-            // Enable status LED to show that joining is in progress
-            status_leds.enable_yellow();
 
-            // Set keys and counters
-            writeln!(
-                debug,
-                "  Stored device address does not match, settings new keys"
-            )
-            .unwrap();
-            rn.set_dev_addr_slice(&config.devaddr)
-                .expect("Could not set dev addr");
-            rn.set_app_session_key_slice(&config.appskey)
-                .expect("Could not set app session key");
-            rn.set_network_session_key_slice(&config.nwkskey)
-                .expect("Could not set network session key");
-            rn.set_data_rate(DataRateEuCn::Sf8Bw125)
-                .expect("Could not set data rate");
-            rn.set_upctr(0).expect("Could not set up counter");
-            rn.set_dnctr(0).expect("Could not set down counter");
-        }
-
-        // Join LoRaWAN network via ABP (this should be instantaneous)
-        match rn.join(JoinMode::Abp) {
+        let mut new_session = false;
+        let join_result = match config.activation {
+            Activation::Abp {
+                devaddr,
+                nwkskey,
+                appskey,
+            } => {
+                writeln!(
+                    debug,
+                    "  Configured dev addr: {:02x}{:02x}{:02x}{:02x}",
+                    devaddr[0], devaddr[1], devaddr[2], devaddr[3],
+                )
+                .unwrap();
+
+                // Check whether credentials have already been configured. Do
+                // this by looking at the device address. If it's still the
+                // default value (0x00000000), or if it's configured with a
+                // different device address, then set new keys. Otherwise, we
+                // can reuse the existing keys.
+                //
+                // (Note: This assumes that the keys for a device with a
+                // specific address do not change. If you want to change the
+                // keys, you must also change the device address.)
+                if dev_addr == devaddr {
+                    writeln!(debug, "  Re-using previously stored credentials").unwrap();
+                } else {
+                    // Enable status LED to show that joining is in progress
+                    status_leds.enable_yellow();
+
+                    // Set keys and counters
+                    writeln!(
+                        debug,
+                        "  Stored device address does not match, settings new keys"
+                    )
+                    .unwrap();
+                    rn.set_dev_addr_slice(&devaddr)
+                        .expect("Could not set dev addr");
+                    rn.set_app_session_key_slice(&appskey)
+                        .expect("Could not set app session key");
+                    rn.set_network_session_key_slice(&nwkskey)
+                        .expect("Could not set network session key");
+                    rn.set_upctr(0).expect("Could not set up counter");
+                    rn.set_dnctr(0).expect("Could not set down counter");
+                    new_session = true;
+                }
+
+                // Join LoRaWAN network via ABP (this should be instantaneous)
+                rn.join(JoinMode::Abp).map_err(|e| {
+                    writeln!(debug, "RN2483: Join failed: {:?}", e).unwrap();
+                })
+            }
+            Activation::Otaa {
+                deveui,
+                joineui,
+                appkey,
+            } => {
+                if woke_from_standby && dev_addr != [0; 4] {
+                    // The session negotiated by a previous join was persisted
+                    // with "mac save" before going to standby. Resume it
+                    // instead of joining again, this is instantaneous and
+                    // does not use any airtime.
+                    writeln!(debug, "  Resuming previously joined OTAA session").unwrap();
+                    rn.join(JoinMode::Abp).map_err(|e| {
+                        writeln!(debug, "RN2483: Resuming session failed: {:?}", e).unwrap();
+                    })
+                } else {
+                    // Enable status LED to show that joining is in progress
+                    status_leds.enable_yellow();
+
+                    writeln!(debug, "  Setting OTAA credentials").unwrap();
+                    rn.set_dev_eui_slice(&deveui)
+                        .expect("Could not set dev EUI");
+                    rn.set_app_eui_slice(&joineui)
+                        .expect("Could not set join EUI");
+                    rn.set_app_key_slice(&appkey)
+                        .expect("Could not set app key");
+
+                    // Join LoRaWAN network via OTAA (this involves a
+                    // join-request / join-accept roundtrip)
+                    if join_otaa(&mut rn, &mut debug, &mut delay) {
+                        new_session = true;
+                        Ok(())
+                    } else {
+                        Err(())
+                    }
+                }
+            }
+        };
+
+        match join_result {
             Ok(()) => {
                 writeln!(debug, "RN2483: Join successful").unwrap();
+
+                // Apply the radio config on cold boot or for a new session.
+                // Otherwise, keep the settings stored with the session (e.g.
+                // the data rate chosen by ADR).
+                if new_session || !woke_from_standby {
+                    apply_radio_config(&mut rn, &mut debug, &config);
+                }
                 status_leds.enable_green();
                 disable_leds::spawn_after(100.millis()).unwrap();
+
+                // After a cold boot, report the device status once
+                if !woke_from_standby {
+                    let probe_id =
+                        status::probe_id(ds18b20.iter().flatten().map(Ds18b20::rom_address));
+                    if let Some(probe_id) = probe_id {
+                        writeln!(debug, "DS18B20 probe ID: {:08X}", probe_id).unwrap();
+                    }
+                    let status_message = StatusMessage {
+                        firmware_version: Version::parse(crate::FIRMWARE_VERSION),
+                        hardware_version: hardware_version.detect_raw(),
+                        rn2483_version: rn_version,
+                        config_version: config.version as u8,
+                        panicked: status.contains(StatusFlags::PANICKED),
+                        probe_id,
+                    };
+                    writeln!(debug, "📣 Transmitting status...").unwrap();
+                    if transmit(
+                        &mut rn,
+                        &mut debug,
+                        &mut flash,
+                        &mut config,
+                        ConfirmationMode::Unconfirmed,
+                        FPORT_STATUS,
+                        &status_message.encode(),
+                    ) {
+                        transmit_pending_panic(&mut rn, &mut debug, &mut flash, &mut config);
+                    }
+                }
             }
-            Err(e) => {
-                writeln!(debug, "RN2483: Join failed: {:?}", e).unwrap();
+            Err(()) => {
                 status_leds.enable_red();
                 disable_leds::spawn_after(1000.millis()).unwrap();
             }
@@ -478,8 +1031,10 @@ mod app {
             },
             LocalResources {
                 base_measurement_plan: measurement_plan,
+                base_status: status,
                 supply_monitor,
                 rn,
+                flash,
                 pwr,
                 scb,
                 rtc,
@@ -495,57 +1050,197 @@ mod app {
     }
 
     /// Start a measurement for both the SHTCx sensor and the DS18B20 sensor.
-    #[task(local = [base_measurement_plan], shared = [debug, delay, sht, one_wire, ds18b20])]
+    ///
+    /// The measurement results are read as soon as the measurements have
+    /// finished. The DS18B20 conversion time depends on the configured
+    /// resolution, so its completion is polled (see
+    /// `poll_ds18b20_conversion`).
+    #[task(
+        local = [base_measurement_plan, base_status],
+        shared = [debug, config, delay, sht, one_wire, ds18b20],
+    )]
     fn start_measurements(ctx: start_measurements::Context) {
         writeln!(ctx.shared.debug, "Starting measurements").unwrap();
         let mut measurement_plan = *ctx.local.base_measurement_plan;
-        ctx.shared
-            .sht
-            .start_measurement(PowerMode::NormalMode)
-            .unwrap_or_else(|_| measurement_plan.measure_sht = false);
-        if let Some(ds18b20) = ctx.shared.ds18b20 {
-            ds18b20
-                .start_measurement(ctx.shared.one_wire, ctx.shared.delay)
-                .unwrap_or_else(|_| measurement_plan.measure_ds18b20 = false);
+        let mut status = *ctx.local.base_status;
+        if measurement_plan.measure_sht {
+            ctx.shared
+                .sht
+                .start_measurement(PowerMode::NormalMode)
+                .unwrap_or_else(|e| {
+                    measurement_plan.measure_sht = false;
+                    status.insert(sht_error_flag(&e));
+                });
+        }
+        if measurement_plan.measure_ds18b20 {
+            if ctx.shared.ds18b20.iter().any(Option::is_some) {
+                // Start the conversion on all probes at once
+                Ds18b20::start_measurement_all(ctx.shared.one_wire, ctx.shared.delay)
+                    .unwrap_or_else(|_| {
+                        measurement_plan.measure_ds18b20 = false;
+                        status.insert(StatusFlags::DS18B20_ERROR);
+                    });
+            } else {
+                measurement_plan.measure_ds18b20 = false;
+            }
+        }
+
+        // The SHTC3 measurement is done after a fixed time. The DS18B20
+        // conversion takes longer, its completion is polled.
+        let delay_ms = crate::SHTC3_MEASUREMENT_TIME_MS;
+        if measurement_plan.measure_ds18b20 {
+            let timeout_ms = ds18b20_conversion_timeout_ms(ctx.shared.config);
+            writeln!(
+                ctx.shared.debug,
+                "Poll DS18B20 conversion (timeout {} ms)",
+                timeout_ms
+            )
+            .unwrap();
+            poll_ds18b20_conversion::spawn_after(
+                delay_ms.millis(),
+                measurement_plan,
+                status,
+                delay_ms,
+                timeout_ms,
+            )
+            .unwrap();
         } else {
-            measurement_plan.measure_ds18b20 = false;
+            writeln!(
+                ctx.shared.debug,
+                "Schedule collection of measurement results in {} ms",
+                delay_ms
+            )
+            .unwrap();
+            read_measurement_results::spawn_after(delay_ms.millis(), measurement_plan, status)
+                .unwrap();
         }
+    }
 
-        // Schedule reading of the measurement results
-        writeln!(
-            ctx.shared.debug,
-            "Schedule collection of measurement results in 500 ms"
-        )
-        .unwrap();
-        read_measurement_results::spawn_after(500.millis(), measurement_plan).unwrap();
+    /// Check whether the DS18B20 conversion is done. If so, read the
+    /// measurement results, otherwise check again later.
+    ///
+    /// If the conversion is not done within `timeout_ms`, the DS18B20
+    /// readings are dropped and the measurement results are read without them.
+    #[task(shared = [debug, delay, one_wire])]
+    fn poll_ds18b20_conversion(
+        ctx: poll_ds18b20_conversion::Context,
+        mut measurement_plan: MeasurementPlan,
+        mut status: StatusFlags,
+        elapsed_ms: u32,
+        timeout_ms: u32,
+    ) {
+        match Ds18b20::is_conversion_done(ctx.shared.one_wire, ctx.shared.delay) {
+            Ok(true) => {
+                writeln!(
+                    ctx.shared.debug,
+                    "DS18B20 conversion done after {} ms",
+                    elapsed_ms
+                )
+                .unwrap();
+            }
+            Ok(false) if elapsed_ms < timeout_ms => {
+                let interval_ms = crate::DS18B20_POLL_INTERVAL_MS;
+                poll_ds18b20_conversion::spawn_after(
+                    interval_ms.millis(),
+                    measurement_plan,
+                    status,
+                    elapsed_ms + interval_ms,
+                    timeout_ms,
+                )
+                .unwrap();
+                return;
+            }
+            result => {
+                if result.is_ok() {
+                    writeln!(ctx.shared.debug, "DS18B20 conversion timed out").unwrap();
+                } else {
+                    writeln!(ctx.shared.debug, "Could not poll DS18B20 conversion").unwrap();
+                }
+                measurement_plan.measure_ds18b20 = false;
+                status.insert(StatusFlags::DS18B20_ERROR);
+            }
+        }
+        read_measurement_results::spawn(measurement_plan, status).unwrap();
     }
 
     /// Read measurement results from the sensors. Re-schedule a measurement.
     #[task(
-        local = [supply_monitor, rn, pwr, scb, rtc],
+        local = [supply_monitor, rn, flash, pwr, scb, rtc],
         shared = [debug, config, delay, sht, one_wire, ds18b20],
     )]
     fn read_measurement_results(
         ctx: read_measurement_results::Context,
         measurement_plan: MeasurementPlan,
+        mut status: StatusFlags,
     ) {
         // Fetch measurement results
         let sht_measurement = if measurement_plan.measure_sht {
-            ctx.shared.sht.get_raw_measurement_result().ok()
-        } else {
-            None
-        };
-        let shtc3_temperature = sht_measurement.as_ref().map(|v| v.temperature);
-        let shtc3_humidity = sht_measurement.as_ref().map(|v| v.humidity);
-        let ds18b20_measurement = if measurement_plan.measure_ds18b20 {
-            ctx.shared.ds18b20.and_then(|ds18b20| {
-                ds18b20
-                    .read_raw_temperature_data(ctx.shared.one_wire, ctx.shared.delay)
-                    .ok()
-            })
+            ctx.shared
+                .sht
+                .get_raw_measurement_result()
+                .map_err(|e| status.insert(sht_error_flag(&e)))
+                .ok()
         } else {
             None
         };
+        let calibration = ctx.shared.config.calibration;
+        let shtc3_temperature = sht_measurement
+            .as_ref()
+            .map(|v| calibration.apply_sht_temperature(v.temperature));
+        let shtc3_humidity = sht_measurement
+            .as_ref()
+            .map(|v| calibration.apply_sht_humidity(v.humidity));
+        let mut ds18b20_measurements = [None; MAX_PROBES];
+        if measurement_plan.measure_ds18b20 {
+            let timeout_ms = ds18b20_conversion_timeout_ms(ctx.shared.config);
+            for (i, (measurement, ds18b20)) in ds18b20_measurements
+                .iter_mut()
+                .zip(ctx.shared.ds18b20.iter())
+                .enumerate()
+            {
+                let ds18b20 = match ds18b20 {
+                    Some(ds18b20) => ds18b20,
+                    None => continue,
+                };
+                let one_wire = &mut *ctx.shared.one_wire;
+                let delay = &mut *ctx.shared.delay;
+                let mut reading = ds18b20.read_temperature(one_wire, delay);
+                if !matches!(reading, Ok(Ds18b20Reading::Valid(_))) {
+                    // Retry once with a new conversion on this probe only
+                    writeln!(
+                        ctx.shared.debug,
+                        "DS18B20 #{}: {:?}, retrying",
+                        i + 1,
+                        reading
+                    )
+                    .unwrap();
+                    reading = ds18b20
+                        .start_measurement(one_wire, delay)
+                        .and_then(|_| Ds18b20::wait_for_conversion(one_wire, timeout_ms, delay))
+                        .and_then(|_| ds18b20.read_temperature(one_wire, delay));
+                }
+                *measurement = match reading {
+                    Ok(Ds18b20Reading::Valid(raw)) => Some(raw),
+                    Ok(reading) => {
+                        writeln!(
+                            ctx.shared.debug,
+                            "DS18B20 #{}: Dropping {:?}",
+                            i + 1,
+                            reading
+                        )
+                        .unwrap();
+                        if let Some(flag) = reading.status_flag() {
+                            status.insert(flag);
+                        }
+                        None
+                    }
+                    Err(_) => {
+                        status.insert(StatusFlags::DS18B20_ERROR);
+                        None
+                    }
+                };
+            }
+        }
 
         // Now that we're done collecting measurement results, put sensors to sleep.
         if let Err(e) = ctx.shared.sht.sleep() {
@@ -564,10 +1259,41 @@ mod app {
 
         // Measure current supply voltage
         let v_supply = if measurement_plan.measure_voltage {
-            ctx.local.supply_monitor.read_supply_u12()
+            ctx.local
+                .supply_monitor
+                .read_supply_u12()
+                .map(|v| calibration.apply_supply(v))
         } else {
             None
         };
+        if let Some(v_supply) = v_supply {
+            if Millivolts::from_supply_raw(v_supply).0 < crate::LOW_BATTERY_THRESHOLD_MV {
+                status.insert(StatusFlags::LOW_BATTERY);
+            }
+        }
+
+        // Assemble measurement message
+        let mut message = MeasurementMessage {
+            t_water: None,
+            t_inside: shtc3_temperature,
+            rh_inside: shtc3_humidity,
+            v_supply,
+            t_water_signed: None,
+            status: if status.is_empty() {
+                None
+            } else {
+                Some(status)
+            },
+            t_water_2: None,
+            t_water_3: None,
+            t_water_4: None,
+        };
+        for (probe, measurement) in ds18b20_measurements.iter().enumerate() {
+            if let Some(raw) = measurement {
+                let raw = calibration.apply_water_temp(probe, *raw);
+                message.set_water_temperature(probe, I12::new(raw));
+            }
+        }
 
         // Print results
         let mut first = true;
@@ -585,40 +1311,48 @@ mod app {
 
         if cfg!(feature = "dev") {
             // Development mode, print human-readable information
-            use shtcx::{Humidity, Temperature};
-            if let Some(ds18b20) = ds18b20_measurement {
-                delimit!();
-                write!(
-                    ctx.shared.debug,
-                    "DS18B20: {:.2}°C (0x{:04x})",
-                    (ds18b20 as f32) / 16.0,
-                    ds18b20,
-                )
-                .unwrap();
+            let decoded = DecodedMeasurement::from(message);
+            let t_water = [
+                decoded.t_water,
+                decoded.t_water_2,
+                decoded.t_water_3,
+                decoded.t_water_4,
+            ];
+            for (i, (t_water, ds18b20)) in
+                t_water.iter().zip(ds18b20_measurements.iter()).enumerate()
+            {
+                if let (Some(t_water), Some(ds18b20)) = (t_water, ds18b20) {
+                    delimit!();
+                    write!(
+                        ctx.shared.debug,
+                        "DS18B20 #{}: {:.2}°C (0x{:04x})",
+                        i + 1,
+                        t_water.0,
+                        ds18b20,
+                    )
+                    .unwrap();
+                }
             }
-            if let Some(sht) = sht_measurement {
+            if let (Some(t_inside), Some(rh_inside)) = (decoded.t_inside, decoded.rh_inside) {
                 delimit!();
                 write!(
                     ctx.shared.debug,
                     "SHTC3: {:.2}°C, {:.2}%RH",
-                    Temperature::from_raw(sht.temperature).as_degrees_celsius(),
-                    Humidity::from_raw(sht.humidity).as_percent(),
+                    t_inside.0, rh_inside.0,
                 )
                 .unwrap();
             }
-            if let Some(v_supply_f32) = v_supply
-                .as_ref()
-                .map(U12::as_u16)
-                .map(SupplyMonitor::convert_input)
-            {
+            if let Some(v_supply) = decoded.v_supply {
                 delimit!();
-                write!(ctx.shared.debug, "VDD: {:.3}V", v_supply_f32).unwrap();
+                write!(ctx.shared.debug, "VDD: {:.3}V", v_supply.0 as f32 / 1000.0).unwrap();
             }
         } else {
             // Production mode, print raw values directly
-            if let Some(ds18b20) = ds18b20_measurement {
-                delimit!();
-                write!(ctx.shared.debug, "DS18B20: 0x{:04x}", ds18b20).unwrap();
+            for (i, ds18b20) in ds18b20_measurements.iter().enumerate() {
+                if let Some(ds18b20) = ds18b20 {
+                    delimit!();
+                    write!(ctx.shared.debug, "DS18B20 #{}: 0x{:04x}", i + 1, ds18b20).unwrap();
+                }
             }
             if let Some(sht) = sht_measurement {
                 delimit!();
@@ -634,42 +1368,139 @@ mod app {
                 write!(ctx.shared.debug, "VDD: 0x{:04x}", v_supply_u12.as_u16(),).unwrap();
             }
         }
+        if !status.is_empty() {
+            delimit!();
+            write!(ctx.shared.debug, "Status: 0b{:016b}", status.bits()).unwrap();
+        }
         writeln!(ctx.shared.debug).unwrap();
 
         if measurement_plan.should_transmit() {
-            let fport = 2;
-
             // Encode measurement
-            let message = MeasurementMessage {
-                t_water: ds18b20_measurement.map(U12::new),
-                t_inside: shtc3_temperature,
-                rh_inside: shtc3_humidity,
-                v_supply,
-            };
             let mut buf = EncodedMeasurement([0u8; MAX_MSG_LEN]);
             let length = message.encode(&mut buf);
 
-            // Transmit
-            writeln!(ctx.shared.debug, "📣 Transmitting measurement...").unwrap();
-            let tx_result = ctx.local.rn.transmit_slice(
-                ConfirmationMode::Unconfirmed,
-                fport,
-                &buf.0[0..length],
-            );
-            match tx_result {
-                Ok(None) => writeln!(ctx.shared.debug, "Uplink succeeded, no downlink").unwrap(),
-                Ok(Some(downlink)) => {
-                    writeln!(ctx.shared.debug, "Downlink: {:?}", downlink).unwrap()
+            let batch_size = (ctx.shared.config.batch_size as usize).min(MAX_BATCH_SIZE);
+            let confirmation_mode =
+                measurement_confirmation_mode(ctx.local.rn, ctx.shared.config.confirmed_every_n);
+            let mut transmitted = false;
+            if batch_size <= 1 {
+                // Transmit
+                writeln!(ctx.shared.debug, "📣 Transmitting measurement...").unwrap();
+                transmitted = transmit(
+                    ctx.local.rn,
+                    ctx.shared.debug,
+                    ctx.local.flash,
+                    ctx.shared.config,
+                    confirmation_mode,
+                    FPORT_MEASUREMENT,
+                    &buf.0[0..length],
+                );
+            } else {
+                // Buffer measurement in EEPROM
+                let uptime = crate::rtc::datetime_to_uptime(ctx.local.rtc.now());
+                let mut sample_buffer = SampleBuffer::new(ctx.local.flash);
+                match sample_buffer.push(uptime, &buf.0[0..length]) {
+                    Ok(()) => writeln!(
+                        ctx.shared.debug,
+                        "Buffered measurement ({}/{})",
+                        sample_buffer.len(),
+                        batch_size,
+                    )
+                    .unwrap(),
+                    Err(e) => {
+                        writeln!(ctx.shared.debug, "Could not buffer measurement: {:?}", e).unwrap()
+                    }
+                }
+
+                if sample_buffer.len() >= batch_size {
+                    // ADR may have lowered the data rate, so the buffered
+                    // measurements are split into as many uplinks as needed.
+                    let max_len = max_payload_len(current_data_rate(ctx.local.rn));
+                    loop {
+                        // Assemble batch from the oldest buffered measurements
+                        let mut batch = BatchMessage::default();
+                        let mut consumed = 0;
+                        for sample in SampleBuffer::new(ctx.local.flash).samples() {
+                            match MeasurementMessage::decode(sample.data()) {
+                                Ok(measurement) => {
+                                    let age_seconds = uptime.saturating_sub(sample.uptime);
+                                    let record = BatchRecord {
+                                        age_seconds: age_seconds.min(u16::MAX as u32) as u16,
+                                        measurement,
+                                    };
+                                    if batch.push_within(record, max_len).is_err() {
+                                        break;
+                                    }
+                                }
+                                Err(e) => writeln!(
+                                    ctx.shared.debug,
+                                    "Skipping invalid buffered measurement: {}",
+                                    e
+                                )
+                                .unwrap(),
+                            }
+                            consumed += 1;
+                        }
+                        if consumed == 0 {
+                            break;
+                        }
+
+                        // Transmit
+                        if !batch.is_empty() {
+                            let mut batch_buf = [0u8; MAX_BATCH_MSG_LEN];
+                            let length = batch.encode(&mut batch_buf);
+                            writeln!(
+                                ctx.shared.debug,
+                                "📣 Transmitting batch of {} measurements...",
+                                batch.len()
+                            )
+                            .unwrap();
+                            if !transmit(
+                                ctx.local.rn,
+                                ctx.shared.debug,
+                                ctx.local.flash,
+                                ctx.shared.config,
+                                confirmation_mode,
+                                FPORT_BATCH,
+                                &batch_buf[0..length],
+                            ) {
+                                // Keep the measurements for the next attempt
+                                break;
+                            }
+                            transmitted = true;
+                        }
+
+                        // Only remove the measurements that were sent (or
+                        // could not be decoded)
+                        SampleBuffer::new(ctx.local.flash)
+                            .remove_oldest(consumed)
+                            .unwrap_or_else(|e| {
+                                writeln!(ctx.shared.debug, "Could not remove samples: {:?}", e)
+                                    .unwrap()
+                            });
+                    }
                 }
-                Err(e) => writeln!(
+            }
+
+            // Report a pending panic after the first successful transmission
+            if transmitted {
+                transmit_pending_panic(
+                    ctx.local.rn,
                     ctx.shared.debug,
-                    "Error: Transmitting LoRaWAN package failed: {:?}",
-                    e
-                )
-                .unwrap(),
+                    ctx.local.flash,
+                    ctx.shared.config,
+                );
             }
         }
 
+        // Confirm a command received in a previous wakeup cycle
+        transmit_pending_ack(
+            ctx.local.rn,
+            ctx.shared.debug,
+            ctx.local.flash,
+            ctx.shared.config,
+        );
+
         // Persist MAC changes
         writeln!(ctx.shared.debug, "RN2483: Calling \"mac save\"").unwrap();
         ctx.local.rn.save_config().unwrap_or_else(|e| {
@@ -0,0 +1,101 @@
+//! Persistent storage for a panic record that has not been transmitted yet.
+//!
+//! The panic message found by `panic_persist` is only held in RAM, which is
+//! lost when the device enters standby mode. Therefore a truncated panic
+//! record is stored in data EEPROM until it has been transmitted.
+//!
+//! ## Memory Map
+//!
+//! Offsets are relative to the start of the
+//! [`PANIC_STORE`](gfroerli_common::memory_map::PANIC_STORE) region.
+//!
+//! ```text
+//!             0           8          16          24          32
+//!             +-----------+-----------+-----------+-----------+
+//! 0x0000      | Length (0 if no record is stored)             |
+//!             +-----------+-----------+-----------+-----------+
+//! 0x0004      | Encoded panic record (up to 36 bytes)         |
+//! ...         |                                               |
+//!             +-----------+-----------+-----------+-----------+
+//! ```
+//!
+//! The length is only written after the record has been written completely.
+
+use core::convert::TryInto;
+
+use gfroerli_common::{
+    memory_map::PANIC_STORE,
+    panic::{PanicRecord, MAX_PANIC_MSG_LEN},
+};
+use stm32l0xx_hal::flash::{self, FLASH};
+
+/// Start address of the panic record in EEPROM
+pub const BASE_ADDR: usize = PANIC_STORE.start;
+
+/// Address of the encoded panic record
+const RECORD_ADDR: usize = BASE_ADDR + 4;
+
+/// Size of the encoded panic record in bytes, rounded up to full words
+const RECORD_SIZE: usize = (MAX_PANIC_MSG_LEN + 3) / 4 * 4;
+
+const _: () = assert!(
+    4 + RECORD_SIZE <= PANIC_STORE.len,
+    "Panic record does not fit into its EEPROM region"
+);
+
+/// Panic record storage in EEPROM.
+///
+/// Note: The store holds a mutable reference to the FLASH peripheral. This
+/// guarantees that no other part of the code can write to EEPROM while the
+/// record is being read.
+pub struct PanicStore<'a> {
+    flash: &'a mut FLASH,
+}
+
+impl<'a> PanicStore<'a> {
+    pub fn new(flash: &'a mut FLASH) -> Self {
+        Self { flash }
+    }
+
+    fn read_bytes(&self, addr: usize, buf: &mut [u8]) {
+        // Note(unsafe): Read with no side effects. This is safe since we hold
+        // a mutable reference to the FLASH peripheral.
+        let data: &[u8] = unsafe { core::slice::from_raw_parts(addr as *const u8, buf.len()) };
+        buf.copy_from_slice(data);
+    }
+
+    fn write_word(&mut self, addr: usize, word: u32) -> Result<(), flash::Error> {
+        self.flash.write_word(addr as *mut u32, word)
+    }
+
+    /// Return the stored panic record, if any.
+    pub fn load(&self) -> Option<PanicRecord> {
+        let mut length = [0; 4];
+        self.read_bytes(BASE_ADDR, &mut length);
+        let length = u32::from_le_bytes(length) as usize;
+        if length == 0 || length > MAX_PANIC_MSG_LEN {
+            return None;
+        }
+        let mut data = [0; MAX_PANIC_MSG_LEN];
+        self.read_bytes(RECORD_ADDR, &mut data[..length]);
+        PanicRecord::decode(&data[..length]).ok()
+    }
+
+    /// Store a panic record, replacing the stored one.
+    pub fn store(&mut self, record: &PanicRecord) -> Result<(), flash::Error> {
+        let mut data = [0; RECORD_SIZE];
+        let length = record.encode((&mut data[..MAX_PANIC_MSG_LEN]).try_into().unwrap());
+        for (i, word) in data.chunks(4).enumerate() {
+            self.write_word(
+                RECORD_ADDR + i * 4,
+                u32::from_le_bytes(word.try_into().unwrap()),
+            )?;
+        }
+        self.write_word(BASE_ADDR, length as u32)
+    }
+
+    /// Remove the stored panic record.
+    pub fn clear(&mut self) -> Result<(), flash::Error> {
+        self.write_word(BASE_ADDR, 0)
+    }
+}
@@ -0,0 +1,285 @@
+//! Persistent buffer for measurement samples that are sent in a batch.
+//!
+//! The RAM contents are lost when the device enters standby mode between two
+//! measurements. Therefore the samples are buffered in data EEPROM.
+//!
+//! ## Memory Map
+//!
+//! Offsets are relative to the start of the
+//! [`SAMPLE_BUFFER`](gfroerli_common::memory_map::SAMPLE_BUFFER) region.
+//!
+//! ```text
+//!             0           8          16          24          32
+//!             +-----------+-----------+-----------+-----------+
+//! 0x0000      | State     | Sequence number                   |
+//! 0x0004      | Uptime                                        |
+//! 0x0008      | Length    | Encoded measurement               |
+//! 0x000C      |                                               |
+//! 0x0010      |                                               |
+//! 0x0014      |                                               |
+//! 0x0018      |                                               |
+//!             +-----------+-----------+-----------+-----------+
+//! 0x001C      | Slot 2 ...                                    |
+//! ```
+//!
+//! The slots are used as a ring buffer. Every slot has its own state (pending
+//! or sent) and a sequence number, which orders the samples and tells where
+//! the next sample goes. There is no shared count word, so every slot header
+//! is only written twice per round through the ring (pushed and sent), which
+//! spreads the EEPROM wear over all slots.
+//!
+//! The header is only written after the rest of the slot has been written
+//! completely. If power is lost while writing a slot, that sample is lost,
+//! but the previously buffered samples remain intact.
+
+use core::convert::TryInto;
+
+use gfroerli_common::{
+    measurement::{MAX_BATCH_SIZE, MAX_MSG_LEN},
+    memory_map::SAMPLE_BUFFER,
+};
+use stm32l0xx_hal::flash::{self, FLASH};
+
+/// Start address of the sample buffer in EEPROM
+pub const BASE_ADDR: usize = SAMPLE_BUFFER.start;
+
+/// Size of a sample slot in bytes: Header (4 bytes), uptime (4 bytes), length
+/// (1 byte) and the encoded measurement, rounded up to full words.
+const SLOT_SIZE: usize = (4 + 4 + 1 + MAX_MSG_LEN + 3) / 4 * 4;
+
+/// Number of slots in the ring buffer
+const SLOT_COUNT: usize = MAX_BATCH_SIZE;
+
+/// Slot state of a sample that has not been sent yet. Any other state byte
+/// (including the erased state) marks an unused slot.
+const STATE_PENDING: u8 = 0xA5;
+
+/// Slot state of a sample that has been sent. The sequence number remains
+/// valid, so that the position in the ring is not lost.
+const STATE_SENT: u8 = 0x5A;
+
+const _: () = assert!(
+    SLOT_COUNT * SLOT_SIZE <= SAMPLE_BUFFER.len,
+    "Sample buffer does not fit into its EEPROM region"
+);
+
+/// A buffered sample.
+pub struct Sample {
+    /// Uptime (in seconds) when the measurement was taken
+    pub uptime: u32,
+    /// The encoded measurement
+    data: [u8; MAX_MSG_LEN],
+    length: usize,
+}
+
+impl Sample {
+    /// Return the encoded measurement.
+    pub fn data(&self) -> &[u8] {
+        &self.data[..self.length]
+    }
+}
+
+#[derive(Debug)]
+pub enum SampleBufferError {
+    /// The encoded measurement is longer than `MAX_MSG_LEN`
+    TooLong,
+    /// Writing to EEPROM failed
+    Flash(flash::Error),
+}
+
+impl From<flash::Error> for SampleBufferError {
+    fn from(e: flash::Error) -> Self {
+        Self::Flash(e)
+    }
+}
+
+/// Return the sequence number that follows `sequence` (24 bits, wrapping).
+fn next_sequence(sequence: u32) -> u32 {
+    (sequence + 1) & 0xFF_FFFF
+}
+
+/// Header of a slot.
+#[derive(Copy, Clone)]
+struct SlotHeader {
+    state: u8,
+    /// Sequence number (24 bits)
+    sequence: u32,
+}
+
+impl SlotHeader {
+    fn is_used(&self) -> bool {
+        self.state == STATE_PENDING || self.state == STATE_SENT
+    }
+
+    fn to_word(self) -> u32 {
+        u32::from(self.state) | self.sequence << 8
+    }
+}
+
+/// Sample buffer in EEPROM.
+///
+/// Note: The buffer holds a mutable reference to the FLASH peripheral. This
+/// guarantees that no other part of the code can write to EEPROM while the
+/// buffer is being read.
+pub struct SampleBuffer<'a> {
+    flash: &'a mut FLASH,
+}
+
+impl<'a> SampleBuffer<'a> {
+    pub fn new(flash: &'a mut FLASH) -> Self {
+        Self { flash }
+    }
+
+    fn read_bytes(&self, addr: usize, buf: &mut [u8]) {
+        // Note(unsafe): Read with no side effects. This is safe since we hold
+        // a mutable reference to the FLASH peripheral.
+        let data: &[u8] = unsafe { core::slice::from_raw_parts(addr as *const u8, buf.len()) };
+        buf.copy_from_slice(data);
+    }
+
+    fn write_word(&mut self, addr: usize, word: u32) -> Result<(), flash::Error> {
+        self.flash.write_word(addr as *mut u32, word)
+    }
+
+    fn slot_addr(slot: usize) -> usize {
+        BASE_ADDR + slot * SLOT_SIZE
+    }
+
+    fn header(&self, slot: usize) -> SlotHeader {
+        let mut header = [0; 4];
+        self.read_bytes(Self::slot_addr(slot), &mut header);
+        SlotHeader {
+            state: header[0],
+            sequence: u32::from_le_bytes(header) >> 8,
+        }
+    }
+
+    fn write_header(&mut self, slot: usize, header: SlotHeader) -> Result<(), flash::Error> {
+        self.write_word(Self::slot_addr(slot), header.to_word())
+    }
+
+    /// Return the most recently written slot and its header.
+    ///
+    /// The slots are written in ring order with consecutive sequence numbers,
+    /// so the newest slot is the one whose successor does not continue the
+    /// sequence. Unlike comparing sequence numbers, this also works when they
+    /// wrap around.
+    fn newest_slot(&self) -> Option<(usize, SlotHeader)> {
+        (0..SLOT_COUNT)
+            .map(|slot| (slot, self.header(slot)))
+            .filter(|(_, header)| header.is_used())
+            .find(|(slot, header)| {
+                let next = self.header((slot + 1) % SLOT_COUNT);
+                !next.is_used() || next.sequence != next_sequence(header.sequence)
+            })
+    }
+
+    /// Return the pending slots, ordered from the oldest to the newest sample.
+    fn pending_slots(&self) -> ([usize; SLOT_COUNT], usize) {
+        let start = self.newest_slot().map_or(0, |(slot, _)| slot + 1);
+        let mut slots = [0; SLOT_COUNT];
+        let mut count = 0;
+        for slot in (start..start + SLOT_COUNT).map(|i| i % SLOT_COUNT) {
+            if self.header(slot).state == STATE_PENDING {
+                slots[count] = slot;
+                count += 1;
+            }
+        }
+        (slots, count)
+    }
+
+    /// Return the number of buffered samples.
+    pub fn len(&self) -> usize {
+        (0..SLOT_COUNT)
+            .filter(|&slot| self.header(slot).state == STATE_PENDING)
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn read_sample(&self, slot: usize) -> Sample {
+        let mut contents = [0; SLOT_SIZE - 4];
+        self.read_bytes(Self::slot_addr(slot) + 4, &mut contents);
+        let mut data = [0; MAX_MSG_LEN];
+        data.copy_from_slice(&contents[5..5 + MAX_MSG_LEN]);
+        Sample {
+            uptime: u32::from_le_bytes(contents[0..4].try_into().unwrap()),
+            data,
+            length: (contents[4] as usize).min(MAX_MSG_LEN),
+        }
+    }
+
+    /// Return the buffered samples, from the oldest to the newest.
+    pub fn samples(&self) -> impl Iterator<Item = Sample> + '_ {
+        let (slots, count) = self.pending_slots();
+        (0..count).map(move |i| self.read_sample(slots[i]))
+    }
+
+    /// Append a sample to the buffer.
+    ///
+    /// If the buffer is full, the oldest sample is overwritten.
+    pub fn push(&mut self, uptime: u32, data: &[u8]) -> Result<(), SampleBufferError> {
+        if data.len() > MAX_MSG_LEN {
+            return Err(SampleBufferError::TooLong);
+        }
+
+        // The next slot follows the most recently written one
+        let (slot, sequence) = match self.newest_slot() {
+            Some((slot, header)) => ((slot + 1) % SLOT_COUNT, next_sequence(header.sequence)),
+            None => (0, 0),
+        };
+
+        // Give up the oldest sample before overwriting its slot
+        let previous = self.header(slot);
+        if previous.state == STATE_PENDING {
+            self.write_header(
+                slot,
+                SlotHeader {
+                    state: STATE_SENT,
+                    ..previous
+                },
+            )?;
+        }
+
+        // Write slot contents
+        let mut contents = [0; SLOT_SIZE - 4];
+        contents[0..4].copy_from_slice(&uptime.to_le_bytes());
+        contents[4] = data.len() as u8;
+        contents[5..5 + data.len()].copy_from_slice(data);
+        let contents_addr = Self::slot_addr(slot) + 4;
+        for (i, word) in contents.chunks(4).enumerate() {
+            self.write_word(
+                contents_addr + i * 4,
+                u32::from_le_bytes(word.try_into().unwrap()),
+            )?;
+        }
+
+        // Write header
+        self.write_header(
+            slot,
+            SlotHeader {
+                state: STATE_PENDING,
+                sequence,
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Remove the `count` oldest samples from the buffer.
+    pub fn remove_oldest(&mut self, count: usize) -> Result<(), flash::Error> {
+        let (slots, pending) = self.pending_slots();
+        for &slot in &slots[..count.min(pending)] {
+            let header = self.header(slot);
+            self.write_header(
+                slot,
+                SlotHeader {
+                    state: STATE_SENT,
+                    ..header
+                },
+            )?;
+        }
+        Ok(())
+    }
+}